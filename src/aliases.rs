@@ -0,0 +1,150 @@
+//! User editable aliases and fallbacks of reference fonts.
+//!
+//! `aliases.json` in a database directory looks like
+//! ```json
+//! {
+//!     "aliases": { "TimesNewRomanPSMT": "TimesNewRoman" },
+//!     "fallbacks": { "Helvetica": ["Arial", "LiberationSans"] }
+//! }
+//! ```
+//! An alias names the reference font to use instead of another one.
+//! A fallback chain lists the reference fonts to try, in order, when there is no database for a font.
+//! Keys are compared with the font name, the name without subset tag and its canonical form
+//! (see `names::normalize_ps_name`). Fallbacks are also looked up by the family of the font,
+//! an alias never is: it would replace every style of the family by the one reference font.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::names::{strip_subset_tag, PsName};
+use crate::store::replace_file;
+use crate::{Error, Result};
+
+pub const ALIAS_FILE: &str = "aliases.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AliasConfig {
+    /// font name → PostScript name of the reference font to use for it
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// font name → reference fonts to try in order
+    #[serde(default)]
+    pub fallbacks: HashMap<String, Vec<String>>,
+}
+impl AliasConfig {
+    /// load the alias file of a database directory, an empty configuration if it has none
+    pub fn load_dir(dir: &Path) -> Result<AliasConfig> {
+        let path = dir.join(ALIAS_FILE);
+        if !path.is_file() {
+            return Ok(AliasConfig::default());
+        }
+        serde_json::from_slice(&std::fs::read(path)?).map_err(Error::Aliases)
+    }
+    pub fn save_dir(&self, dir: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(Error::Aliases)?;
+        replace_file(&dir.join(ALIAS_FILE), &data)
+    }
+    /// the explicit alias of `name`
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        keys(name, false).find_map(|k| self.aliases.get(&k)).map(|s| s.as_str())
+    }
+    /// the fallback chain of `name` and the key it was found under
+    pub fn fallbacks(&self, name: &str) -> Option<(String, &[String])> {
+        keys(name, true).find_map(|k| self.fallbacks.get(&k).map(|chain| (k, chain.as_slice())))
+    }
+}
+
+/// keys `name` is looked up by, most specific first, ending with its family if `family` is set
+fn keys(name: &str, family: bool) -> impl Iterator<Item=String> {
+    let parsed = PsName::parse(name);
+    let mut keys: Vec<String> = vec![];
+    let family = if family { Some(parsed.family.clone()) } else { None };
+    for k in [name.to_string(), strip_subset_tag(name).to_string(), parsed.canonical()].into_iter().chain(family) {
+        if !k.is_empty() && !keys.contains(&k) {
+            keys.push(k);
+        }
+    }
+    keys.into_iter()
+}
+
+/// how the requested font name was mapped to a reference font
+#[derive(Clone, Debug, PartialEq)]
+pub enum Resolution {
+    /// a database with exactly the requested name
+    Exact,
+    /// the name after removing the subset tag or normalizing the style
+    Normalized,
+    /// an explicit alias in the alias file
+    Alias,
+    /// entry `position` of the fallback chain of `key`
+    Fallback { key: String, position: usize },
+}
+
+/// reference font used for a requested font name
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resolved {
+    pub ps_name: String,
+    pub resolution: Resolution,
+}
+impl fmt::Display for Resolved {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.resolution {
+            Resolution::Exact => write!(f, "{}", self.ps_name),
+            Resolution::Normalized => write!(f, "{} (normalized name)", self.ps_name),
+            Resolution::Alias => write!(f, "{} (alias)", self.ps_name),
+            Resolution::Fallback { ref key, position } => write!(f, "{} (fallback {} for {})", self.ps_name, position, key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::db_dir;
+    use crate::FontDb;
+
+    fn config() -> AliasConfig {
+        AliasConfig {
+            aliases: [("Helvetica", "ArialMT"), ("Helvetica-Bold", "Arial-BoldMT")]
+                .into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+            fallbacks: [("Helvetica".into(), vec!["Missing".into(), "ArialMT".into()])].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn alias_keys() {
+        let config = config();
+        assert_eq!(config.alias("Helvetica"), Some("ArialMT"));
+        assert_eq!(config.alias("ABCDEF+Helvetica-Bold"), Some("Arial-BoldMT"));
+        // the family only selects fallbacks
+        assert_eq!(config.alias("Helvetica-Oblique"), None);
+        let (key, chain) = config.fallbacks("Helvetica-Oblique").unwrap();
+        assert_eq!(key, "Helvetica");
+        assert_eq!(chain, ["Missing", "ArialMT"]);
+        assert!(config.fallbacks("Courier").is_none());
+    }
+
+    #[test]
+    fn resolve() {
+        let dir = db_dir("resolve", &["ArialMT", "Arial-BoldMT", "Helvetica-Bold"]);
+        let db = FontDb::new(&dir);
+        db.set_aliases(config());
+        let resolve = |name: &str| db.resolve(name).unwrap();
+
+        // a database with the exact name wins over an alias
+        assert_eq!(resolve("Helvetica-Bold"), Some(Resolved { ps_name: "Helvetica-Bold".into(), resolution: Resolution::Exact }));
+        assert_eq!(resolve("ABCDEF+Helvetica-Bold"), Some(Resolved { ps_name: "Arial-BoldMT".into(), resolution: Resolution::Alias }));
+        assert_eq!(resolve("Helvetica"), Some(Resolved { ps_name: "ArialMT".into(), resolution: Resolution::Alias }));
+        assert_eq!(resolve("ABCDEF+ArialMT"), Some(Resolved { ps_name: "ArialMT".into(), resolution: Resolution::Normalized }));
+        // the alias of the family does not replace the style
+        let fallback = Resolution::Fallback { key: "Helvetica".into(), position: 1 };
+        assert_eq!(resolve("Helvetica-Oblique"), Some(Resolved { ps_name: "ArialMT".into(), resolution: fallback }));
+        assert_eq!(resolve("Courier"), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+//! Minimum cost one-to-one assignment (Hungarian algorithm).
+
+/// costs above this are treated as equal, so infinite costs do not break the arithmetic
+const MAX_COST: f64 = 1e12;
+
+/// Assign each row of the `rows` x `cols` cost matrix `cost` (row major) to at most one column,
+/// so that every column is used at most once and the total cost is minimal.
+///
+/// Returns the assigned column of every row. If there are more rows than columns,
+/// some rows remain unassigned.
+pub fn assign(cost: &[f32], rows: usize, cols: usize) -> Vec<Option<usize>> {
+    assert_eq!(cost.len(), rows * cols);
+    let n = rows.max(cols);
+    // missing rows or columns are padded with zero cost
+    let c = |i: usize, j: usize| -> f64 {
+        if i < rows && j < cols {
+            (cost[i * cols + j] as f64).min(MAX_COST)
+        } else {
+            0.
+        }
+    };
+
+    // potentials and matching are 1-based, index 0 is a sentinel
+    let mut u = vec![0.0f64; n + 1];
+    let mut v = vec![0.0f64; n + 1];
+    let mut p = vec![0usize; n + 1];
+    let mut way = vec![0usize; n + 1];
+
+    for i in 1 ..= n {
+        p[0] = i;
+        let mut j0 = 0;
+        let mut minv = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1 ..= n {
+                if used[j] {
+                    continue;
+                }
+                let cur = c(i0 - 1, j - 1) - u[i0] - v[j];
+                if cur < minv[j] {
+                    minv[j] = cur;
+                    way[j] = j0;
+                }
+                if minv[j] < delta {
+                    delta = minv[j];
+                    j1 = j;
+                }
+            }
+            for j in 0 ..= n {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if p[j0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+    for (j, &i) in p.iter().enumerate().skip(1) {
+        if i >= 1 && i - 1 < rows && j - 1 < cols {
+            assignment[i - 1] = Some(j - 1);
+        }
+    }
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(cost: &[f32], cols: usize, assignment: &[Option<usize>]) -> f32 {
+        assignment.iter().enumerate().filter_map(|(i, j)| Some(cost[i * cols + (*j)?])).sum()
+    }
+
+    #[test]
+    fn square() {
+        let cost = [
+            4., 1., 3.,
+            2., 0., 5.,
+            3., 2., 2.,
+        ];
+        let assignment = assign(&cost, 3, 3);
+        assert_eq!(assignment, [Some(1), Some(0), Some(2)]);
+        assert_eq!(total(&cost, 3, &assignment), 5.);
+    }
+
+    #[test]
+    fn rectangular() {
+        // more columns than rows: every row is assigned
+        let cost = [
+            9., 1., 9., 9.,
+            1., 9., 9., 9.,
+        ];
+        assert_eq!(assign(&cost, 2, 4), [Some(1), Some(0)]);
+
+        // more rows than columns: one row is left out
+        let cost = [
+            5., 5.,
+            1., 9.,
+            9., 1.,
+        ];
+        assert_eq!(assign(&cost, 3, 2), [None, Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn infinite_costs() {
+        let inf = f32::INFINITY;
+        let cost = [
+            inf, 1.,
+            2., inf,
+        ];
+        assert_eq!(assign(&cost, 2, 2), [Some(1), Some(0)]);
+        assert_eq!(assign(&[inf; 4], 2, 2).iter().flatten().count(), 2);
+    }
+
+    #[test]
+    fn empty() {
+        assert!(assign(&[], 0, 0).is_empty());
+        assert_eq!(assign(&[], 2, 0), [None, None]);
+    }
+}
@@ -0,0 +1,267 @@
+//! Checking all fonts of a document at once.
+//!
+//! Documents often embed many subsets of the same fonts. Glyphs with identical outlines
+//! that are matched against the same reference font are only looked up once.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use font::{Font, GlyphId};
+use istring::SmallString;
+use pathfinder_content::outline::Outline;
+use pathfinder_geometry::transform2d::Transform2F;
+
+use crate::global::GlobalIndex;
+use crate::memo::ResultCache;
+use crate::normalize::transform_to_array;
+use crate::{FontDb, FontMatch, ReferenceDb, Result};
+
+/// statistics of `FontDb::check_fonts`
+#[derive(Clone, Debug, Default)]
+pub struct BatchStats {
+    pub fonts: usize,
+    /// glyphs with an outline, over all fonts
+    pub glyphs: usize,
+    /// distinct outlines that were looked up
+    pub lookups: usize,
+    /// glyphs that were labeled
+    pub matched: usize,
+    /// fonts without a database of their own, matched against all reference fonts
+    pub global: usize,
+    /// labeled glyphs per reference font, most first
+    pub sources: Vec<(String, usize)>,
+}
+
+/// result of `FontDb::check_fonts`
+#[derive(Clone, Debug, Default)]
+pub struct BatchResult {
+    /// one result per font, in the order they were given
+    pub fonts: Vec<FontMatch>,
+    pub stats: BatchStats,
+}
+
+/// what a font is matched against
+enum Target {
+    /// a reference font and its name
+    Font(String, Arc<ReferenceDb>),
+    Global(Arc<GlobalIndex>),
+}
+impl Target {
+    /// fonts with the same database share lookups
+    fn same_db(&self, other: &Target) -> bool {
+        match (self, other) {
+            (Target::Font(_, a), Target::Font(_, b)) => Arc::ptr_eq(a, b),
+            (Target::Global(_), Target::Global(_)) => true,
+            _ => false,
+        }
+    }
+    /// label and reference font of the glyph `outline`
+    fn lookup(&self, memo: Option<&ResultCache>, outline: &Outline, font_matrix: Transform2F) -> Option<(SmallString, String)> {
+        match self {
+            Target::Font(ps_name, db) => {
+                let label = match memo {
+                    Some(memo) => memo.get_or_lookup(db, outline, font_matrix),
+                    None => db.get(outline, font_matrix, None),
+                };
+                label.map(|s| (s, ps_name.clone()))
+            }
+            Target::Global(index) => {
+                let label = match memo {
+                    Some(memo) => index.get_or_lookup(memo, outline, font_matrix),
+                    None => index.get(outline, font_matrix, None),
+                };
+                label.map(|l| {
+                    let source = index.font_name(&l).into();
+                    (l.label, source)
+                })
+            }
+        }
+    }
+}
+
+/// Exact description of an outline and font matrix.
+///
+/// Two glyphs with the same key give the same result for every lookup.
+fn outline_key(outline: &Outline, font_matrix: Transform2F) -> Vec<u32> {
+    let mut key: Vec<u32> = transform_to_array(font_matrix).iter().map(|f| f.to_bits()).collect();
+    for contour in outline.contours() {
+        key.push(contour.len() | (contour.is_closed() as u32) << 31);
+        for i in 0 .. contour.len() {
+            let p = contour.position_of(i);
+            key.extend([p.x().to_bits(), p.y().to_bits(), contour.point_is_endpoint(i) as u32]);
+        }
+    }
+    key
+}
+
+/// the glyphs of a font that have an outline
+struct FontGlyphs<'a> {
+    ps_name: &'a str,
+    font_matrix: Transform2F,
+    glyphs: Vec<(GlyphId, Outline)>,
+}
+
+/// a distinct outline to look up
+struct Query {
+    target: usize,
+    outline: Outline,
+    font_matrix: Transform2F,
+}
+
+#[cfg(not(feature = "parallel"))]
+fn run_queries(targets: &[Target], memo: Option<&ResultCache>, queries: &[Query]) -> Vec<Option<(SmallString, String)>> {
+    queries.iter().map(|q| targets[q.target].lookup(memo, &q.outline, q.font_matrix)).collect()
+}
+
+#[cfg(feature = "parallel")]
+fn run_queries(targets: &[Target], memo: Option<&ResultCache>, queries: &[Query]) -> Vec<Option<(SmallString, String)>> {
+    use rayon::prelude::*;
+
+    queries.par_iter().map(|q| targets[q.target].lookup(memo, &q.outline, q.font_matrix)).collect()
+}
+
+fn count_sources(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut sources: Vec<_> = counts.into_iter().collect();
+    sources.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    sources
+}
+
+impl FontDb {
+    /// Label the glyphs of several fonts, each with the name of its reference font (see `check_font`).
+    ///
+    /// Identical outlines are only looked up once per reference font.
+    pub fn check_fonts(&self, fonts: &[(&str, &(dyn Font + Sync + Send))]) -> Result<BatchResult> {
+        let fonts = fonts.iter().map(|&(ps_name, font)| FontGlyphs {
+            ps_name,
+            font_matrix: font.font_matrix(),
+            glyphs: (0 .. font.num_glyphs()).filter_map(|i| {
+                let g = font.glyph(GlyphId(i))?;
+                (!g.path.is_empty()).then_some((GlyphId(i), g.path))
+            }).collect(),
+        }).collect();
+        self.check_outlines(fonts)
+    }
+
+    fn check_outlines(&self, fonts: Vec<FontGlyphs>) -> Result<BatchResult> {
+        let mut stats = BatchStats { fonts: fonts.len(), ..BatchStats::default() };
+
+        // the target of every font, fonts that use the same database share one
+        let mut targets: Vec<Target> = vec![];
+        let mut font_targets = Vec::with_capacity(fonts.len());
+        let mut font_resolved = Vec::with_capacity(fonts.len());
+        for font in &fonts {
+            let (resolved, target) = match self.get_db(font.ps_name)? {
+                Some((resolved, db)) => {
+                    let target = Target::Font(resolved.ps_name.clone(), db);
+                    (Some(resolved), target)
+                }
+                None => {
+                    stats.global += 1;
+                    (None, Target::Global(self.global_index()?))
+                }
+            };
+            let idx = match targets.iter().position(|t| t.same_db(&target)) {
+                Some(idx) => idx,
+                None => {
+                    targets.push(target);
+                    targets.len() - 1
+                }
+            };
+            font_targets.push(idx);
+            font_resolved.push(resolved);
+        }
+
+        // the query of every glyph
+        let mut queries: Vec<Query> = vec![];
+        let mut query_ids: HashMap<(usize, Vec<u32>), usize> = HashMap::new();
+        let mut glyph_queries: Vec<Vec<(GlyphId, usize)>> = Vec::with_capacity(fonts.len());
+        for (font, &target) in fonts.into_iter().zip(font_targets.iter()) {
+            let mut glyphs = vec![];
+            for (gid, outline) in font.glyphs {
+                stats.glyphs += 1;
+                let key = (target, outline_key(&outline, font.font_matrix));
+                let id = *query_ids.entry(key).or_insert_with(|| {
+                    queries.push(Query { target, outline, font_matrix: font.font_matrix });
+                    queries.len() - 1
+                });
+                glyphs.push((gid, id));
+            }
+            glyph_queries.push(glyphs);
+        }
+        stats.lookups = queries.len();
+
+        let memo = self.result_cache();
+        let answers = run_queries(&targets, memo.as_deref(), &queries);
+
+        let mut total: HashMap<String, usize> = HashMap::new();
+        let mut results = Vec::with_capacity(glyph_queries.len());
+        for (glyphs, resolved) in glyph_queries.into_iter().zip(font_resolved) {
+            let mut labels = HashMap::new();
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for (gid, id) in glyphs {
+                if let Some((ref label, ref source)) = answers[id] {
+                    labels.insert(gid, label.clone());
+                    *counts.entry(source.clone()).or_default() += 1;
+                    *total.entry(source.clone()).or_default() += 1;
+                }
+            }
+            stats.matched += labels.len();
+            results.push(FontMatch { glyphs: labels, sources: count_sources(counts), resolved });
+        }
+        stats.sources = count_sources(total);
+
+        Ok(BatchResult { fonts: results, stats })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::aliases::{AliasConfig, Resolution};
+    use crate::test_util::{db_dir, matrix, outline, shapes};
+
+    fn glyphs(outlines: impl IntoIterator<Item=Outline>) -> Vec<(GlyphId, Outline)> {
+        outlines.into_iter().enumerate().map(|(i, o)| (GlyphId(i as u32 + 1), o)).collect()
+    }
+
+    #[test]
+    fn shared_lookups() {
+        let dir = db_dir("batch", &["ArialMT", "Arial-BoldMT"]);
+        let db = FontDb::new(&dir);
+        db.set_aliases(AliasConfig {
+            aliases: [("Helvetica".into(), "ArialMT".into())].into_iter().collect(),
+            ..AliasConfig::default()
+        });
+        let shapes = shapes();
+        let unknown = outline(&[&[(0., 0.), (900., 0.), (0., 50.)]]);
+        let font = |ps_name, outlines: Vec<Outline>| FontGlyphs { ps_name, font_matrix: matrix(), glyphs: glyphs(outlines) };
+        let result = db.check_outlines(vec![
+            font("ArialMT", vec![shapes[0].0.clone(), shapes[1].0.clone(), unknown.clone()]),
+            font("ABCDEF+ArialMT", vec![shapes[0].0.clone(), unknown]),
+            font("Helvetica", vec![shapes[1].0.clone(), shapes[2].0.clone()]),
+            font("Arial-BoldMT", vec![shapes[0].0.clone()]),
+        ]).unwrap();
+
+        // every font keeps its own resolution, even when it shares the database
+        let resolutions: Vec<_> = result.fonts.iter().map(|f| f.resolved.as_ref().map(|r| (r.ps_name.as_str(), r.resolution.clone()))).collect();
+        assert_eq!(resolutions, [
+            Some(("ArialMT", Resolution::Exact)),
+            Some(("ArialMT", Resolution::Normalized)),
+            Some(("ArialMT", Resolution::Alias)),
+            Some(("Arial-BoldMT", Resolution::Exact)),
+        ]);
+        assert_eq!(result.fonts[0].glyphs.get(&GlyphId(1)).map(|s| &**s), Some("square"));
+        assert_eq!(result.fonts[0].glyphs.get(&GlyphId(3)), None);
+        assert_eq!(result.fonts[2].glyphs.get(&GlyphId(2)).map(|s| &**s), Some("L"));
+        assert_eq!(result.fonts[1].sources, [("ArialMT".to_string(), 1)]);
+
+        let stats = &result.stats;
+        assert_eq!((stats.fonts, stats.glyphs, stats.matched, stats.global), (4, 8, 6, 0));
+        // square, triangle, L and the unknown outline for ArialMT, square for Arial-BoldMT
+        assert_eq!(stats.lookups, 5);
+        assert_eq!(stats.sources, [("ArialMT".to_string(), 5), ("Arial-BoldMT".to_string(), 1)]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -4,5 +4,5 @@ use glyphmatcher::{FontDb};
 
 fn main() {
     let db = FontDb::new(Path::new("db"));
-    db.scan();
+    db.scan().unwrap();
 }
\ No newline at end of file
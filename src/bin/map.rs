@@ -0,0 +1,10 @@
+use glyphmatcher::FontDb;
+
+/// rewrite all databases in the `db` directory in the mapped layout
+fn main() {
+    let db = FontDb::new("db");
+    for e in db.catalog().unwrap() {
+        println!("{}", e.ps_name);
+        db.map_font(&e.ps_name).unwrap();
+    }
+}
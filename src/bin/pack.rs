@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+use glyphmatcher::FontDb;
+
+/// pack the `db` directory into the file given as first argument and list its contents
+fn main() {
+    let out = PathBuf::from(std::env::args_os().nth(1).expect("usage: pack <output file>"));
+
+    FontDb::new("db").pack(&out).unwrap();
+
+    let db = FontDb::open(&out).unwrap();
+    for e in db.catalog().unwrap() {
+        println!("{}\t{}\t{}\t{} glyphs",
+            e.ps_name,
+            e.family.as_deref().unwrap_or("-"),
+            e.style.as_deref().unwrap_or("-"),
+            e.glyph_count
+        );
+    }
+}
@@ -0,0 +1,17 @@
+use glyphmatcher::FontDb;
+
+/// rebuild the databases of the fonts given as arguments, or of all fonts in the catalog,
+/// from the font files recorded in the catalog of the `db` directory
+fn main() {
+    let db = FontDb::new("db");
+    let mut names: Vec<String> = std::env::args().skip(1).collect();
+    if names.is_empty() {
+        names = db.catalog().unwrap().into_iter().map(|e| e.ps_name).collect();
+    }
+    for ps_name in names {
+        println!("{ps_name}");
+        if let Err(e) = db.rebuild(&ps_name) {
+            println!("  {e}");
+        }
+    }
+}
@@ -1,7 +1,7 @@
-use std::{fs::File, io::{BufWriter, Write}, path::Path, collections::HashMap};
+use std::{fs::File, io::{BufWriter, Write}, collections::HashMap};
 
 use font::{Glyph, Font};
-use glyphmatcher::{frechet::frechet_distance, min, UnicodeList, UnicodeEntry};
+use glyphmatcher::{frechet::cyclic_frechet_distance, min, UnicodeList, UnicodeEntry};
 use pathfinder_content::outline::Outline;
 
 fn read_font(path: &str) -> Box<dyn Font + Sync + Send> {
@@ -9,7 +9,7 @@ fn read_font(path: &str) -> Box<dyn Font + Sync + Send> {
     font::parse(&data).unwrap()
 }
 fn shapes(font: &dyn Font) -> Vec<(u32, Glyph)> {
-    (1 .. font.num_glyphs()).filter_map(|n| Some((n, font.glyph(font::GlyphId(n))?))).filter(|(_, g)| g.path.len() > 0).collect()
+    (1 .. font.num_glyphs()).filter_map(|n| Some((n, font.glyph(font::GlyphId(n))?))).filter(|(_, g)| !g.path.is_empty()).collect()
 }
 
 fn read_uni(path: &str) -> HashMap<u32, Vec<u32>> {
@@ -61,7 +61,7 @@ svg {
         }
         dists.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
 
-        for &(b_gid, score) in dists.iter().take(1) {
+        for &(b_gid, _score) in dists.iter().take(1) {
             if let Some(uni) = uni_b.get(b_gid) {
                 uni_a.push(UnicodeEntry { gid: a_gid, unicode: uni.clone() });                
             }
@@ -70,16 +70,16 @@ svg {
 
     std::fs::write(
         "unicode/axtmanalblack.json",
-        &serde_json::to_string(&uni_a).unwrap()
+        serde_json::to_string(&uni_a).unwrap()
     ).unwrap();
 }
 
 fn glyph_similarity(a: &Glyph, b: &Glyph) -> f32 {
     let mut dists = vec![];
-    for (t_i, t_c) in a.path.contours().iter().enumerate() {
+    for t_c in a.path.contours().iter() {
         let mut min_score = f32::INFINITY;
-        for (r_i, r_c) in b.path.contours().iter().enumerate() {
-            min_score = min(min_score, frechet_distance(t_c, r_c));
+        for r_c in b.path.contours().iter() {
+            min_score = min(min_score, cyclic_frechet_distance(t_c, r_c));
         }
         dists.push(min_score);
     }
@@ -1,6 +1,9 @@
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
-use glyphmatcher::{FontDb};
+use glyphmatcher::FontDb;
+use glyphmatcher::layers::LabelSource;
+use glyphmatcher::tounicode::ToUnicode;
+use glyphmatcher::validate::DEFAULT_THRESHOLD;
 
 
 fn main() {
@@ -10,11 +13,43 @@ fn main() {
     let data = std::fs::read(&path).unwrap();
     
     let font = font::parse(&data).unwrap();
-    let ps_name: &str = font.name().postscript_name.as_deref().unwrap_or_else(|| {
-        path.file_name().unwrap().to_str().unwrap()
-    }).split("+").nth(1).unwrap();
+    println!("embedded name: {:?}", font.name().postscript_name);
+
+    let resolved = match font.name().postscript_name.as_deref() {
+        Some(name) => db.resolve(name).unwrap(),
+        None => None,
+    };
+    let ps_name = match resolved {
+        Some(resolved) => {
+            println!("resolved: {}", resolved);
+            resolved.ps_name
+        }
+        None => {
+            let candidates = db.identify_font(&*font).unwrap();
+            for c in candidates.iter().take(5) {
+                println!("{}: {}/{} glyphs ({:.0}%), {} unique", c.ps_name, c.matched, c.tested, c.coverage * 100., c.unique);
+            }
+            let Some(best) = candidates.into_iter().next() else {
+                println!("no matching reference font");
+                return;
+            };
+            best.ps_name
+        }
+    };
+    let ps_name = ps_name.as_str();
     println!("name: {:?}", ps_name);
-    let report = db.font_report(ps_name, &*font);
+    let report = db.font_report(ps_name, &*font).unwrap();
 
     std::fs::write(path.with_extension("html"), report).unwrap();
-}
\ No newline at end of file
+
+    let validation = db.validate_font(ps_name, &*font, DEFAULT_THRESHOLD).unwrap();
+    for (source, v) in validation.sources.iter() {
+        println!("{:?} labels: {}/{} agree{}", source, v.agreed, v.compared, if v.unreliable { ", unreliable" } else { "" });
+    }
+
+    let result = db.check_font_layered(ps_name, &*font).unwrap();
+    for source in [LabelSource::GlyphName, LabelSource::CMap, LabelSource::Shape] {
+        println!("{:?}: {} glyphs", source, result.count(source));
+    }
+    std::fs::write(path.with_extension("cmap"), ToUnicode::from_glyphs(&result.glyphs()).unwrap().write()).unwrap();
+}
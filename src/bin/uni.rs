@@ -1,7 +1,6 @@
 use std::{path::Path, fs::read_dir};
 
 use glyphmatcher::{font_uni_list, UnicodeEntry, UnicodeList};
-use serde::{Serialize, Deserialize};
 
 fn main() {
     let fonts_path = Path::new("fonts");
@@ -18,7 +17,7 @@ fn main() {
 
         let data = std::fs::read(&path).unwrap();
         if let Ok(font) = font::parse(&data) {
-            if let Some(name_list) = font_uni_list(&*font, use_name) {
+            if let Ok(name_list) = font_uni_list(&*font, use_name) {
                 let name_list: UnicodeList = name_list.into_iter()
                     .map(|(gid, uni)| UnicodeEntry { gid: gid.0, unicode: uni.chars().map(|c| c as u32).collect() })
                     .collect();
@@ -0,0 +1,391 @@
+//! Shared state of `FontDb`.
+//!
+//! With the `parallel` feature reads are lock free: the maps are split into shards by key,
+//! and a write replaces the shard of its key using `arc_swap`.
+//! Without it they are behind a `RwLock`.
+//! Locks are taken with `read` and `write`, which ignore poisoning: every write leaves the maps
+//! in a valid state, so a panic in another thread is no reason to fail all later calls.
+//!
+//! Caches are bounded by a `CachePolicy`. Reads only bump the last-used counter of the entry,
+//! the least recently used entries are evicted when an entry is inserted.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "parallel")]
+use arc_swap::{ArcSwap, ArcSwapOption};
+
+#[cfg(feature = "parallel")]
+use crate::hash::hash_bytes;
+
+/// lock `lock` for reading, even if it is poisoned
+pub(crate) fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+/// lock `lock` for writing, even if it is poisoned
+pub(crate) fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Limits of the database cache of `FontDb`, all unlimited by default.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CachePolicy {
+    /// maximum number of cached fonts, including fonts that were not found
+    pub max_entries: Option<usize>,
+    /// Maximum total size of the cached databases, measured by the size of their files.
+    ///
+    /// This is not the memory they use: a database read into memory takes more than its file,
+    /// a memory mapped one only takes what was paged in.
+    pub max_bytes: Option<u64>,
+    /// how long a font that was not found is remembered, `None` keeps it until `FontDb::invalidate`
+    pub negative_ttl: Option<Duration>,
+}
+
+struct Entry<V> {
+    value: V,
+    /// size in bytes, counted against `CachePolicy::max_bytes`
+    size: u64,
+    expires: Option<Instant>,
+    /// value of `Cache::clock` when the entry was last read
+    last_used: AtomicU64,
+}
+impl<V> Entry<V> {
+    fn expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|t| t <= now)
+    }
+}
+
+type Entries<V> = HashMap<String, Arc<Entry<V>>>;
+
+/// Keys to remove so that `entries` are within the limits of `policy`:
+/// expired entries, then the least recently used ones.
+///
+/// `keep` is never evicted, so a single entry larger than `max_bytes` stays cached.
+fn victims<'a, V: 'a>(entries: impl Iterator<Item=(&'a String, &'a Arc<Entry<V>>)>, policy: &CachePolicy, keep: Option<&str>) -> Vec<(String, Arc<Entry<V>>)> {
+    let now = Instant::now();
+    let mut victims = vec![];
+    let mut lru = vec![];
+    let mut len = 0;
+    let mut bytes = 0;
+    for (key, e) in entries {
+        if e.expired(now) {
+            victims.push((key.clone(), e.clone()));
+            continue;
+        }
+        len += 1;
+        bytes += e.size;
+        if Some(key.as_str()) != keep {
+            lru.push((e.last_used.load(Ordering::Relaxed), key, e));
+        }
+    }
+    let over = |len: usize, bytes: u64| {
+        policy.max_entries.is_some_and(|max| len > max) || policy.max_bytes.is_some_and(|max| bytes > max)
+    };
+    if !over(len, bytes) {
+        return victims;
+    }
+    lru.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(b.1)));
+    for (_, key, e) in lru {
+        if !over(len, bytes) {
+            break;
+        }
+        len -= 1;
+        bytes -= e.size;
+        victims.push((key.clone(), e.clone()));
+    }
+    victims
+}
+
+/// number of shards of the map with the `parallel` feature
+#[cfg(feature = "parallel")]
+const SHARDS: usize = 16;
+
+/// part of the map, replaced as a whole on every write
+#[cfg(feature = "parallel")]
+struct Shard<V> {
+    entries: Entries<V>,
+    /// total size of `entries`
+    bytes: u64,
+}
+#[cfg(feature = "parallel")]
+impl<V> Clone for Shard<V> {
+    fn clone(&self) -> Self {
+        Shard { entries: self.entries.clone(), bytes: self.bytes }
+    }
+}
+#[cfg(feature = "parallel")]
+impl<V> Default for Shard<V> {
+    fn default() -> Self {
+        Shard { entries: HashMap::new(), bytes: 0 }
+    }
+}
+#[cfg(feature = "parallel")]
+impl<V> Shard<V> {
+    fn insert(&mut self, key: String, entry: Arc<Entry<V>>) {
+        self.bytes += entry.size;
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.bytes -= old.size;
+        }
+    }
+    /// remove `key` if it still is `entry`
+    fn remove(&mut self, key: &str, entry: Option<&Arc<Entry<V>>>) {
+        let current = self.entries.get(key);
+        if current.is_some_and(|e| entry.is_none_or(|entry| Arc::ptr_eq(e, entry))) {
+            let old = self.entries.remove(key).unwrap();
+            self.bytes -= old.size;
+        }
+    }
+}
+
+/// map from font names to `V`
+pub(crate) struct Cache<V> {
+    /// a write only copies the shard of its key
+    #[cfg(feature = "parallel")]
+    shards: [ArcSwap<Shard<V>>; SHARDS],
+    #[cfg(not(feature = "parallel"))]
+    map: RwLock<Entries<V>>,
+    clock: AtomicU64,
+    policy: Slot<CachePolicy>,
+}
+impl<V> Default for Cache<V> {
+    fn default() -> Self {
+        Cache {
+            #[cfg(feature = "parallel")]
+            shards: std::array::from_fn(|_| Default::default()),
+            #[cfg(not(feature = "parallel"))]
+            map: Default::default(),
+            clock: Default::default(),
+            policy: Default::default(),
+        }
+    }
+}
+impl<V> Cache<V> {
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+    pub fn policy(&self) -> CachePolicy {
+        self.policy.get().map(|p| CachePolicy::clone(&p)).unwrap_or_default()
+    }
+    /// `value` is the value of `key`, `negative` values expire after `CachePolicy::negative_ttl`
+    fn entry(&self, value: V, size: u64, negative: bool) -> Arc<Entry<V>> {
+        let ttl = if negative { self.policy().negative_ttl } else { None };
+        Arc::new(Entry {
+            value,
+            size,
+            expires: ttl.map(|ttl| Instant::now() + ttl),
+            last_used: AtomicU64::new(self.tick()),
+        })
+    }
+    fn read(&self, entry: &Entry<V>) -> Option<V> where V: Clone {
+        if entry.expires.is_some() && entry.expired(Instant::now()) {
+            return None;
+        }
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(entry.value.clone())
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<V: Clone> Cache<V> {
+    fn shard(&self, key: &str) -> &ArcSwap<Shard<V>> {
+        &self.shards[hash_bytes(key.as_bytes()) as usize % SHARDS]
+    }
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.read(self.shard(key).load().entries.get(key)?)
+    }
+    /// Insert `value` and evict entries as needed.
+    ///
+    /// `size` is counted against `CachePolicy::max_bytes`, `negative` values expire after `CachePolicy::negative_ttl`.
+    pub fn insert(&self, key: String, value: V, size: u64, negative: bool) {
+        let entry = self.entry(value, size, negative);
+        let now = Instant::now();
+        self.shard(&key).rcu(|shard| {
+            // the shard is copied anyway, drop its expired entries on the way
+            let mut shard = Shard::clone(shard);
+            shard.entries.retain(|_, e| !e.expired(now));
+            shard.bytes = shard.entries.values().map(|e| e.size).sum();
+            shard.insert(key.clone(), entry.clone());
+            shard
+        });
+        let policy = self.policy();
+        let (len, bytes) = self.usage();
+        if policy.max_entries.is_some_and(|max| len > max) || policy.max_bytes.is_some_and(|max| bytes > max) {
+            self.evict(&policy, Some(&key));
+        }
+    }
+    /// remove the entries chosen by `victims`, unless they were replaced in the meantime
+    fn evict(&self, policy: &CachePolicy, keep: Option<&str>) {
+        let shards: Vec<_> = self.shards.iter().map(|s| s.load()).collect();
+        let victims = victims(shards.iter().flat_map(|s| s.entries.iter()), policy, keep);
+        drop(shards);
+        for (key, entry) in victims {
+            self.shard(&key).rcu(|shard| {
+                let mut shard = Shard::clone(shard);
+                shard.remove(&key, Some(&entry));
+                shard
+            });
+        }
+    }
+    pub fn remove(&self, key: &str) {
+        self.shard(key).rcu(|shard| {
+            let mut shard = Shard::clone(shard);
+            shard.remove(key, None);
+            shard
+        });
+    }
+    /// keep only the entries for which `f` returns true
+    pub fn retain(&self, f: impl Fn(&str, &V) -> bool) {
+        for shard in self.shards.iter() {
+            shard.rcu(|shard| {
+                let mut shard = Shard::clone(shard);
+                shard.entries.retain(|key, e| f(key, &e.value));
+                shard.bytes = shard.entries.values().map(|e| e.size).sum();
+                shard
+            });
+        }
+    }
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.store(Default::default());
+        }
+    }
+    /// use `policy` from now on, evicting entries that exceed it
+    pub fn set_policy(&self, policy: CachePolicy) {
+        self.evict(&policy, None);
+        self.policy.set(Some(Arc::new(policy)));
+    }
+    /// number of entries and their total size
+    pub fn usage(&self) -> (usize, u64) {
+        self.shards.iter().map(|s| s.load()).fold((0, 0), |(len, bytes), s| (len + s.entries.len(), bytes + s.bytes))
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+impl<V: Clone> Cache<V> {
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.read(read(&self.map).get(key)?)
+    }
+    /// Insert `value` and evict entries as needed.
+    ///
+    /// `size` is counted against `CachePolicy::max_bytes`, `negative` values expire after `CachePolicy::negative_ttl`.
+    pub fn insert(&self, key: String, value: V, size: u64, negative: bool) {
+        let entry = self.entry(value, size, negative);
+        let policy = self.policy();
+        let mut map = write(&self.map);
+        map.insert(key.clone(), entry);
+        evict(&mut map, &policy, Some(&key));
+    }
+    pub fn remove(&self, key: &str) {
+        write(&self.map).remove(key);
+    }
+    /// keep only the entries for which `f` returns true
+    pub fn retain(&self, f: impl Fn(&str, &V) -> bool) {
+        write(&self.map).retain(|key, e| f(key, &e.value));
+    }
+    pub fn clear(&self) {
+        write(&self.map).clear();
+    }
+    /// use `policy` from now on, evicting entries that exceed it
+    pub fn set_policy(&self, policy: CachePolicy) {
+        evict(&mut write(&self.map), &policy, None);
+        self.policy.set(Some(Arc::new(policy)));
+    }
+    /// number of entries and their total size
+    pub fn usage(&self) -> (usize, u64) {
+        let map = read(&self.map);
+        (map.len(), map.values().map(|e| e.size).sum())
+    }
+}
+
+/// remove the entries of `map` chosen by `victims`
+#[cfg(not(feature = "parallel"))]
+fn evict<V>(map: &mut Entries<V>, policy: &CachePolicy, keep: Option<&str>) {
+    let victims = victims(map.iter(), policy, keep);
+    for (key, _) in victims {
+        map.remove(&key);
+    }
+}
+
+/// a value that is computed on first use and can be reset
+pub(crate) struct Slot<T> {
+    #[cfg(feature = "parallel")]
+    value: ArcSwapOption<T>,
+    #[cfg(not(feature = "parallel"))]
+    value: RwLock<Option<Arc<T>>>,
+}
+impl<T> Default for Slot<T> {
+    fn default() -> Self {
+        Slot { value: Default::default() }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<T> Slot<T> {
+    pub fn get(&self) -> Option<Arc<T>> {
+        self.value.load_full()
+    }
+    pub fn set(&self, value: Option<Arc<T>>) {
+        self.value.store(value);
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+impl<T> Slot<T> {
+    pub fn get(&self) -> Option<Arc<T>> {
+        read(&self.value).clone()
+    }
+    pub fn set(&self, value: Option<Arc<T>>) {
+        *write(&self.value) = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_least_recently_used() {
+        let cache = Cache::default();
+        cache.set_policy(CachePolicy { max_entries: Some(2), ..CachePolicy::default() });
+        cache.insert("a".into(), 1, 10, false);
+        cache.insert("b".into(), 2, 10, false);
+        assert_eq!(cache.get("a"), Some(1));
+        cache.insert("c".into(), 3, 10, false);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.usage(), (2, 20));
+
+        cache.set_policy(CachePolicy { max_bytes: Some(15), ..CachePolicy::default() });
+        assert_eq!(cache.usage(), (1, 10));
+        assert_eq!(cache.get("a"), Some(1));
+        // an entry larger than the limit stays until the next insert
+        cache.insert("d".into(), 4, 100, false);
+        assert_eq!(cache.usage(), (1, 100));
+    }
+
+    #[test]
+    fn retain_and_remove() {
+        let cache = Cache::default();
+        for (i, key) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            cache.insert(key.into(), i, 1, false);
+        }
+        cache.retain(|_, &v| v % 2 == 0);
+        assert_eq!(cache.usage(), (2, 2));
+        cache.remove("c");
+        assert_eq!(cache.get("a"), Some(0));
+        assert_eq!(cache.get("c"), None);
+        assert_eq!(cache.usage(), (1, 1));
+    }
+
+    #[test]
+    fn negative_entries_expire() {
+        let cache = Cache::default();
+        cache.set_policy(CachePolicy { negative_ttl: Some(Duration::ZERO), ..CachePolicy::default() });
+        cache.insert("missing".into(), None, 0, true);
+        cache.insert("found".into(), Some(1), 0, false);
+        assert_eq!(cache.get("missing"), None);
+        assert_eq!(cache.get("found"), Some(Some(1)));
+    }
+}
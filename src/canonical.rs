@@ -0,0 +1,60 @@
+//! Canonical form of contours.
+//!
+//! Converting between TrueType and CFF often reverses the winding of contours
+//! and rotates their start point. Canonical contours wind counter-clockwise and
+//! start at their lowest on-curve point (smallest y, then smallest x).
+
+use std::cmp::Ordering;
+
+use pathfinder_content::outline::Contour;
+use pathfinder_geometry::vector::Vector2F;
+
+/// twice the signed area of the closed polygon, positive for counter-clockwise winding
+pub fn signed_area(points: &[Vector2F]) -> f32 {
+    if points.len() < 3 {
+        return 0.;
+    }
+    let mut sum = 0.;
+    for (i, &a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        sum += a.x() * b.y() - b.x() * a.y();
+    }
+    sum
+}
+
+fn cmp_points(a: Vector2F, b: Vector2F) -> Ordering {
+    a.y().total_cmp(&b.y()).then(a.x().total_cmp(&b.x()))
+}
+
+/// bring `points` into canonical form, considering only points where `is_start(i)` as start points
+pub fn canonicalize_with(points: &mut [Vector2F], is_start: impl Fn(usize) -> bool) {
+    if points.is_empty() {
+        return;
+    }
+    let mut start_candidates: Vec<bool> = (0 .. points.len()).map(is_start).collect();
+    if !start_candidates.iter().any(|&b| b) {
+        start_candidates.iter_mut().for_each(|b| *b = true);
+    }
+    if signed_area(points) < 0. {
+        points.reverse();
+        start_candidates.reverse();
+    }
+    let start = points.iter().enumerate()
+        .filter(|&(i, _)| start_candidates[i])
+        .min_by(|a, b| cmp_points(*a.1, *b.1))
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    points.rotate_left(start);
+}
+
+/// bring a polyline into canonical form
+pub fn canonicalize(points: &mut [Vector2F]) {
+    canonicalize_with(points, |_| true);
+}
+
+/// canonical points of `contour`, starting at an on-curve point
+pub fn canonical_points(contour: &Contour) -> Vec<Vector2F> {
+    let mut points = contour.points().to_vec();
+    canonicalize_with(&mut points, |i| contour.point_is_endpoint(i as u32));
+    points
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::format::FormatError;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// a font file could not be parsed
+    FontParse { path: PathBuf, message: String },
+    /// a database file could not be read or written
+    Database(FormatError),
+    /// a file is damaged, for example a pack whose index points past its end
+    Corrupt { path: PathBuf, message: String },
+    /// a label file in the `unicode` directory is invalid
+    LabelFile(serde_json::Error),
+    /// the catalog of a database directory is invalid
+    Catalog(serde_json::Error),
+    /// the alias file of a database directory is invalid
+    Aliases(serde_json::Error),
+    /// the database is a pack file, which cannot be modified
+    ReadOnly,
+    /// no unicode labels could be found for a font
+    MissingLabels(String),
+    /// there is no database for the font
+    UnknownFont(String),
+    /// character codes of a CMap must be 1 to 4 bytes long
+    InvalidCodeLength(usize),
+    /// a character code does not fit in the code length of a CMap
+    CodeOutOfRange { code: u32, code_bytes: usize },
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::FontParse { path, message } => write!(f, "failed to parse font {path:?}: {message}"),
+            Error::Database(e) => e.fmt(f),
+            Error::Corrupt { path, message } => write!(f, "{path:?} is corrupt: {message}"),
+            Error::LabelFile(e) => write!(f, "invalid label file: {e}"),
+            Error::Catalog(e) => write!(f, "invalid catalog: {e}"),
+            Error::Aliases(e) => write!(f, "invalid alias file: {e}"),
+            Error::ReadOnly => write!(f, "packed databases cannot be modified"),
+            Error::MissingLabels(name) => write!(f, "no unicode labels for font {name}"),
+            Error::UnknownFont(name) => write!(f, "no database for font {name}"),
+            Error::InvalidCodeLength(n) => write!(f, "invalid character code length {n}, must be 1 to 4 bytes"),
+            Error::CodeOutOfRange { code, code_bytes } => write!(f, "character code {code:#X} does not fit in {code_bytes} bytes"),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Database(e) => Some(e),
+            Error::LabelFile(e) => Some(e),
+            Error::Catalog(e) => Some(e),
+            Error::Aliases(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+impl From<FormatError> for Error {
+    fn from(e: FormatError) -> Self {
+        Error::Database(e)
+    }
+}
@@ -0,0 +1,140 @@
+//! Flattening and resampling of outlines.
+//!
+//! TrueType outlines use quadratic and CFF outlines cubic curves, so the control points
+//! of the same glyph differ between both. Resampled contours only consist of on-curve
+//! points spaced evenly along the contour, which makes both comparable.
+
+use pathfinder_content::outline::{Contour, ContourIterFlags, Outline};
+use pathfinder_geometry::vector::Vector2F;
+
+use crate::canonical::canonicalize_with;
+
+/// number of line segments each curve is split into while flattening
+const CURVE_STEPS: usize = 16;
+
+/// Approximate `contour` by a closed polyline.
+///
+/// Also returns which of the points were end points of segments of the contour.
+pub fn flatten_contour(contour: &Contour) -> (Vec<Vector2F>, Vec<bool>) {
+    let mut points = vec![];
+    let mut endpoints = vec![];
+    for segment in contour.iter(ContourIterFlags::empty()) {
+        points.push(segment.baseline.from());
+        endpoints.push(true);
+        if segment.is_line() {
+            continue;
+        }
+        let cubic = segment.to_cubic();
+        for i in 1 .. CURVE_STEPS {
+            points.push(cubic.as_cubic_segment().sample(i as f32 / CURVE_STEPS as f32));
+            endpoints.push(false);
+        }
+    }
+    (points, endpoints)
+}
+
+/// Resample the closed polyline `points` into points that are evenly spaced
+/// roughly `spacing` apart, starting at the first point.
+pub fn resample(points: &[Vector2F], spacing: f32) -> Vec<Vector2F> {
+    if points.len() < 2 || spacing <= 0. {
+        return points.to_vec();
+    }
+    let n = points.len();
+    let lengths: Vec<f32> = (0 .. n).map(|i| (points[(i + 1) % n] - points[i]).length()).collect();
+    let total: f32 = lengths.iter().sum();
+    if total <= 0. {
+        return vec![points[0]];
+    }
+    // the step is derived from the total length, so slightly different lengths
+    // still produce the same number of samples at proportional positions
+    let count = ((total / spacing).round() as usize).max(3);
+    let step = total / count as f32;
+
+    let mut samples = Vec::with_capacity(count);
+    let mut seg = 0;
+    let mut seg_start = 0.;
+    for k in 0 .. count {
+        let d = k as f32 * step;
+        while seg + 1 < n && seg_start + lengths[seg] < d {
+            seg_start += lengths[seg];
+            seg += 1;
+        }
+        let a = points[seg];
+        let b = points[(seg + 1) % n];
+        let t = if lengths[seg] > 0. { ((d - seg_start) / lengths[seg]).clamp(0., 1.) } else { 0. };
+        samples.push(a + (b - a) * t);
+    }
+    samples
+}
+
+/// Replace every contour of `outline` by evenly spaced on-curve samples.
+///
+/// Contours are brought into canonical form first, so resampling starts at the same point
+/// independent of the start point and direction of the original contour.
+pub fn resample_outline(outline: &Outline, spacing: f32) -> Outline {
+    let mut out = Outline::new();
+    for contour in outline.contours() {
+        let (mut points, endpoints) = flatten_contour(contour);
+        canonicalize_with(&mut points, |i| endpoints[i]);
+        let samples = resample(&points, spacing);
+        let mut c = Contour::with_capacity(samples.len());
+        for p in samples {
+            c.push_endpoint(p);
+        }
+        c.close();
+        out.push_contour(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::outline;
+
+    fn v(x: f32, y: f32) -> Vector2F {
+        Vector2F::new(x, y)
+    }
+
+    fn points(outline: &Outline) -> Vec<Vector2F> {
+        outline.contours()[0].points().to_vec()
+    }
+
+    #[test]
+    fn even_spacing() {
+        let square = [v(0., 0.), v(10., 0.), v(10., 10.), v(0., 10.)];
+        let samples = resample(&square, 4.);
+        // the perimeter of 40 gives 10 samples
+        assert_eq!(samples.len(), 10);
+        assert_eq!(samples[0], v(0., 0.));
+        for i in 0 .. samples.len() {
+            let d = (samples[(i + 1) % samples.len()] - samples[i]).length();
+            assert!(d <= 4. + 1e-4, "{d}");
+        }
+        assert_eq!(samples[1], v(4., 0.));
+        assert_eq!(samples[3], v(10., 2.));
+    }
+
+    #[test]
+    fn degenerate() {
+        assert_eq!(resample(&[v(1., 1.)], 1.), [v(1., 1.)]);
+        assert_eq!(resample(&[v(1., 1.), v(1., 1.)], 1.), [v(1., 1.)]);
+        // short contours keep at least three points
+        assert_eq!(resample(&[v(0., 0.), v(1., 0.), v(0., 1.)], 100.).len(), 3);
+    }
+
+    #[test]
+    fn independent_of_start_and_direction() {
+        let square = [(0., 0.), (10., 0.), (10., 10.), (0., 10.)];
+        let expected = points(&resample_outline(&outline(&[&square]), 3.));
+
+        let mut rotated = square;
+        rotated.rotate_left(2);
+        assert_eq!(points(&resample_outline(&outline(&[&rotated]), 3.)), expected);
+
+        let mut reversed = square;
+        reversed.reverse();
+        assert_eq!(points(&resample_outline(&outline(&[&reversed]), 3.)), expected);
+    }
+}
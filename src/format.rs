@@ -0,0 +1,267 @@
+//! On-disk format of a `ShapeDb`.
+//!
+//! Files start with `MAGIC`, followed by the format version as a little endian `u16`,
+//! the postcard encoded `Header` and the postcard encoded database.
+//!
+//! Files without the magic that decode as the layout used before the format was versioned are
+//! rejected with `FormatError::Legacy`. Their point keys were truncated to `u16`, so they cannot
+//! be upgraded. Like files of other versions they have to be rebuilt from their font file,
+//! see `FontDb::rebuild` and the `rebuild` tool.
+//! Any other file without the magic is not a database.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::hash::hash_bytes;
+use crate::{BuildParams, ShapeDb};
+
+pub const MAGIC: [u8; 4] = *b"GMDB";
+pub const VERSION: u16 = 5;
+
+/// describes the content of a database file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Header {
+    /// hash of the font file the database was built from
+    pub source_hash: u64,
+    pub params: BuildParams,
+    /// length of the encoded database following the header
+    pub payload_len: u64,
+    /// hash of the encoded database
+    pub checksum: u64,
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    Decode(postcard::Error),
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidMagic,
+    /// the file was written before the format was versioned
+    Legacy,
+    Checksum { expected: u64, found: u64 },
+    /// the database was built from a different font file than the one in the catalog
+    SourceMismatch { expected: u64, found: u64 },
+}
+impl From<postcard::Error> for FormatError {
+    fn from(e: postcard::Error) -> Self {
+        FormatError::Decode(e)
+    }
+}
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Decode(e) => write!(f, "failed to decode database: {e}"),
+            FormatError::UnsupportedVersion(v) => write!(f, "unsupported database version {v} (expected {VERSION}), rebuild it with `FontDb::rebuild`"),
+            FormatError::Truncated => write!(f, "database file is truncated"),
+            FormatError::InvalidMagic => write!(f, "not a database file"),
+            FormatError::Legacy => write!(f, "database predates the versioned format, rebuild it with `FontDb::rebuild`"),
+            FormatError::Checksum { expected, found } => write!(f, "database checksum mismatch (expected {expected:016x}, found {found:016x})"),
+            FormatError::SourceMismatch { expected, found } => write!(f, "database was built from a different font file (expected {expected:016x}, found {found:016x}), rebuild it with `FontDb::rebuild`"),
+        }
+    }
+}
+impl std::error::Error for FormatError {}
+
+/// layout of databases written before the format was versioned
+#[derive(Deserialize)]
+struct LegacyEntry<I> {
+    #[allow(dead_code)]
+    contour_sets: Vec<HashSet<(u16, u16)>>,
+    #[allow(dead_code)]
+    data: I,
+}
+#[derive(Deserialize)]
+struct LegacyShapeDb<I> {
+    #[allow(dead_code)]
+    entries: Vec<LegacyEntry<I>>,
+    #[allow(dead_code)]
+    points: HashMap<(u16, u16), Vec<usize>>,
+}
+
+/// the error for `data` without the magic, `Legacy` only if all of it decodes in the legacy layout
+fn unversioned<I: DeserializeOwned>(data: &[u8]) -> FormatError {
+    match postcard::take_from_bytes::<LegacyShapeDb<I>>(data) {
+        Ok((_, [])) => FormatError::Legacy,
+        _ => FormatError::InvalidMagic,
+    }
+}
+
+impl<I: Serialize> ShapeDb<I> {
+    /// encode the database, `source_hash` identifies the font it was built from
+    pub fn to_bytes(&self, source_hash: u64) -> Result<Vec<u8>, FormatError> {
+        let payload = postcard::to_allocvec(self)?;
+        let header = Header {
+            source_hash,
+            params: self.params,
+            payload_len: payload.len() as u64,
+            checksum: hash_bytes(&payload),
+        };
+        let mut data = Vec::with_capacity(payload.len() + 64);
+        data.extend_from_slice(&MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        let mut data = postcard::to_extend(&header, data)?;
+        data.extend_from_slice(&payload);
+        Ok(data)
+    }
+}
+impl<I: DeserializeOwned> ShapeDb<I> {
+    /// decode a database
+    pub fn from_bytes(data: &[u8]) -> Result<Self, FormatError> {
+        Self::from_bytes_with_header(data).map(|(_, db)| db)
+    }
+    /// decode a database and its header
+    pub fn from_bytes_with_header(data: &[u8]) -> Result<(Header, Self), FormatError> {
+        if !data.starts_with(&MAGIC) {
+            return Err(unversioned::<I>(data));
+        }
+        let (header, payload) = split_header(data)?;
+        let checksum = hash_bytes(payload);
+        if checksum != header.checksum {
+            return Err(FormatError::Checksum { expected: header.checksum, found: checksum });
+        }
+        let mut db: ShapeDb<I> = postcard::from_bytes(payload)?;
+        db.content_hash = Some(checksum);
+        Ok((header, db))
+    }
+}
+
+/// Read the header of a database file without decoding the database.
+pub fn read_header(data: &[u8]) -> Result<Header, FormatError> {
+    if !data.starts_with(&MAGIC) {
+        return Err(unversioned::<String>(data));
+    }
+    split_header(data).map(|(header, _)| header)
+}
+
+/// Read the header and the number of entries of a database file.
+///
+/// Only the start of the file is needed, the entries are not decoded.
+/// Files without the magic are reported as `InvalidMagic`, telling legacy files apart needs all of the file.
+pub fn read_summary(data: &[u8]) -> Result<(Header, usize), FormatError> {
+    if !data.starts_with(&MAGIC) {
+        return Err(FormatError::InvalidMagic);
+    }
+    let (header, payload) = take_header(data)?;
+    // the payload starts with the `params` and the length of `entries` of the `ShapeDb`
+    let (_, rest): (BuildParams, _) = postcard::take_from_bytes(payload)?;
+    let (len, _): (usize, _) = postcard::take_from_bytes(rest)?;
+    Ok((header, len))
+}
+
+fn take_header(data: &[u8]) -> Result<(Header, &[u8]), FormatError> {
+    let rest = &data[MAGIC.len() ..];
+    if rest.len() < 2 {
+        return Err(FormatError::Truncated);
+    }
+    let version = u16::from_le_bytes([rest[0], rest[1]]);
+    if version != VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+    Ok(postcard::take_from_bytes(&rest[2..])?)
+}
+
+fn split_header(data: &[u8]) -> Result<(Header, &[u8]), FormatError> {
+    let (header, payload) = take_header(data)?;
+    if payload.len() as u64 != header.payload_len {
+        return Err(FormatError::Truncated);
+    }
+    Ok((header, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::store::{Catalog, CatalogEntry};
+    use crate::test_util::{db, db_dir, matrix, shapes};
+    use crate::{Error, FontDb};
+
+    #[test]
+    fn round_trip() {
+        let data = db().to_bytes(42).unwrap();
+        let (header, decoded) = ShapeDb::<String>::from_bytes_with_header(&data).unwrap();
+        assert_eq!(header.source_hash, 42);
+        assert_eq!(header.params, *db().params());
+        assert_eq!(decoded.len(), shapes().len());
+        assert!(decoded.cache_key().is_some());
+        for (outline, label) in shapes() {
+            assert_eq!(decoded.get(&outline, matrix(), None).map(|s| s.as_str()), Some(label));
+        }
+
+        assert_eq!(read_header(&data).unwrap(), header);
+        let (summary, len) = read_summary(&data[.. data.len().min(64)]).unwrap();
+        assert_eq!((summary, len), (header, shapes().len()));
+    }
+
+    #[test]
+    fn reject_legacy() {
+        #[derive(Serialize)]
+        struct Entry {
+            contour_sets: Vec<HashSet<(u16, u16)>>,
+            data: String,
+        }
+        #[derive(Serialize)]
+        struct Legacy {
+            entries: Vec<Entry>,
+            points: HashMap<(u16, u16), Vec<usize>>,
+        }
+        let legacy = Legacy {
+            entries: vec![Entry { contour_sets: vec![[(1, 2), (3, 4)].into_iter().collect()], data: "a".into() }],
+            points: [((1, 2), vec![0]), ((3, 4), vec![0])].into_iter().collect(),
+        };
+        let data = postcard::to_allocvec(&legacy).unwrap();
+        assert!(matches!(ShapeDb::<String>::from_bytes(&data), Err(FormatError::Legacy)));
+        assert!(matches!(read_header(&data), Err(FormatError::Legacy)));
+
+        assert!(matches!(ShapeDb::<String>::from_bytes(b"not a database at all"), Err(FormatError::InvalidMagic)));
+    }
+
+    #[test]
+    fn reject_damaged() {
+        let data = db().to_bytes(0).unwrap();
+
+        let mut corrupt = data.clone();
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        assert!(matches!(ShapeDb::<String>::from_bytes(&corrupt), Err(FormatError::Checksum { .. })));
+
+        let truncated = &data[.. data.len() - 1];
+        assert!(matches!(ShapeDb::<String>::from_bytes(truncated), Err(FormatError::Truncated)));
+        assert!(matches!(ShapeDb::<String>::from_bytes(&data[.. 5]), Err(FormatError::Truncated)));
+
+        let mut newer = data.clone();
+        newer[MAGIC.len() .. MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(ShapeDb::<String>::from_bytes(&newer), Err(FormatError::UnsupportedVersion(v)) if v == VERSION + 1));
+    }
+
+    fn catalog(fonts: &[(&str, u64)]) -> Catalog {
+        let fonts = fonts.iter().map(|&(ps_name, source_hash)| CatalogEntry {
+            ps_name: ps_name.into(),
+            family: None,
+            style: None,
+            version: None,
+            glyph_count: shapes().len(),
+            source_path: String::new(),
+            source_hash,
+        }).collect();
+        Catalog { fonts }
+    }
+
+    #[test]
+    fn check_source_hash() {
+        let dir = db_dir("source-hash", &["A", "B"]);
+        catalog(&[("A", 0), ("B", 9)]).save_dir(&dir).unwrap();
+        let fonts = FontDb::new(&dir);
+        assert!(fonts.load_db("A").unwrap().is_some());
+        assert!(matches!(fonts.load_db("B"), Err(Error::Database(FormatError::SourceMismatch { expected: 9, found: 0 }))));
+        // there is no font file to rebuild it from
+        assert!(matches!(fonts.rebuild("B"), Err(Error::UnknownFont(_))));
+
+        // the catalog is read again after `invalidate`
+        catalog(&[("A", 0), ("B", 0)]).save_dir(&dir).unwrap();
+        fonts.invalidate("B");
+        assert!(fonts.load_db("B").unwrap().is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,6 +1,5 @@
-use itertools::Itertools;
 use pathfinder_geometry::{vector::Vector2F};
-use pathfinder_content::outline::{Contour, Outline};
+use pathfinder_content::outline::Contour;
 
 use crate::{max, min};
 
@@ -8,19 +7,7 @@ fn euclidean_distance(a: Vector2F, b: Vector2F) -> f32 {
     (a - b).length()
 }
 
-fn curve_length(contour: &Contour) -> f32 {
-    contour.points().iter().cloned().tuple_windows().map(|(a, b)| euclidean_distance(a, b)).sum()
-}
-
-fn extend_point_on_line(a: Vector2F, b: Vector2F, dist: f32) -> Vector2F {
-    let norm = dist / euclidean_distance(a, b);
-    b + (a - b) * norm
-}
-
-fn calc_value(i: usize, j: usize, prev_results_col: &[f32], current_results_col: &[f32], long_curve: &Contour, short_curve: &Contour) -> f32 {
-    let long_curve = long_curve.points();
-    let short_curve = short_curve.points();
-
+fn calc_value(i: usize, j: usize, prev_results_col: &[f32], current_results_col: &[f32], long_curve: &[Vector2F], short_curve: &[Vector2F]) -> f32 {
     if i == 0 && j == 0 {
         return euclidean_distance(long_curve[0], short_curve[0]);
     }
@@ -38,9 +25,17 @@ fn calc_value(i: usize, j: usize, prev_results_col: &[f32], current_results_col:
 }
 
 pub fn frechet_distance(curve1: &Contour, curve2: &Contour) -> f32 {
+    frechet_distance_points(curve1.points(), curve2.points())
+}
+
+/// discrete frechet distance between two polylines
+pub fn frechet_distance_points(curve1: &[Vector2F], curve2: &[Vector2F]) -> f32 {
+    if curve1.is_empty() || curve2.is_empty() {
+        return f32::INFINITY;
+    }
     let longcalcurve;
     let shortcalcurve;
-    if curve1.points().len() > curve2.points().len() {
+    if curve1.len() > curve2.len() {
         longcalcurve = curve1;
         shortcalcurve = curve2
     } else {
@@ -49,9 +44,9 @@ pub fn frechet_distance(curve1: &Contour, curve2: &Contour) -> f32 {
     }
 
     let mut prev_resultscalcol = vec![];
-    for i in 0 .. longcalcurve.points().len() as usize {
+    for i in 0 .. longcalcurve.len() {
         let mut current_resultscalcol = vec![];
-        for j in 0 .. shortcalcurve.points().len() as usize {
+        for j in 0 .. shortcalcurve.len() {
             current_resultscalcol.push(
                 calc_value(
                     i, j, &prev_resultscalcol, 
@@ -62,5 +57,36 @@ pub fn frechet_distance(curve1: &Contour, curve2: &Contour) -> f32 {
         }
         prev_resultscalcol = current_resultscalcol;
     }
-    prev_resultscalcol[shortcalcurve.points().len() as usize - 1]
+    prev_resultscalcol[shortcalcurve.len() - 1]
+}
+
+fn closed(points: impl Iterator<Item=Vector2F>) -> Vec<Vector2F> {
+    let mut v: Vec<Vector2F> = points.collect();
+    if let Some(&first) = v.first() {
+        v.push(first);
+    }
+    v
+}
+
+/// frechet distance between two closed contours, independent of start point and direction
+pub fn cyclic_frechet_distance(curve1: &Contour, curve2: &Contour) -> f32 {
+    cyclic_frechet_distance_points(curve1.points(), curve2.points())
+}
+
+/// frechet distance between two closed polylines,
+/// minimized over all start points and both directions of `curve2`
+pub fn cyclic_frechet_distance_points(curve1: &[Vector2F], curve2: &[Vector2F]) -> f32 {
+    if curve1.is_empty() || curve2.is_empty() {
+        return f32::INFINITY;
+    }
+    let a = closed(curve1.iter().cloned());
+    let n = curve2.len();
+    let mut best = f32::INFINITY;
+    for shift in 0 .. n {
+        let forward = closed((0 .. n).map(|i| curve2[(shift + i) % n]));
+        best = min(best, frechet_distance_points(&a, &forward));
+        let backward = closed((0 .. n).map(|i| curve2[(shift + n - i) % n]));
+        best = min(best, frechet_distance_points(&a, &backward));
+    }
+    best
 }
@@ -0,0 +1,293 @@
+//! Index over the glyphs of all fonts in a database.
+//!
+//! Used for embedded fonts whose PostScript name does not identify a reference font.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hasher;
+use std::sync::{Arc, OnceLock};
+
+use font::{Font, GlyphId};
+use istring::SmallString;
+use pathfinder_content::outline::Outline;
+use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_geometry::vector::Vector2F;
+
+use crate::index::PointKey;
+use crate::canonical::canonical_points;
+use crate::hash::Fnv1a;
+use crate::memo::{cache_key, ResultCache};
+use crate::lookup::{candidates, find, fuzzy_distance, match_contours, points_set, prepare, FuzzyIndex, ShapeData};
+use crate::{default_fuzzy_threshold, match_glyphs, BuildParams, FontMatch, ReferenceDb, Result};
+
+/// version of the numbering of the entries, part of `GlobalIndex::cache_key`
+const INDEX_VERSION: u16 = 1;
+
+/// weight of the vote of a fuzzy match in `GlobalIndex::identify`, relative to an exact match
+const FUZZY_WEIGHT: f32 = 0.5;
+
+/// label of a glyph in the global index
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlobalLabel {
+    /// index of the reference font in `GlobalIndex::fonts`
+    pub font: u32,
+    pub label: SmallString,
+}
+impl fmt::Display for GlobalLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.label.fmt(f)
+    }
+}
+
+/// a reference font that may be the source of an embedded font
+#[derive(Clone, Debug)]
+pub struct FontCandidate {
+    pub ps_name: String,
+    /// glyphs of the embedded font that match a glyph of this font
+    pub matched: usize,
+    /// matched glyphs that match no other reference font
+    pub unique: usize,
+    /// glyphs of the embedded font that were compared
+    pub tested: usize,
+    /// `matched / tested`
+    pub coverage: f32,
+    /// glyphs in the database of this font
+    pub reference_glyphs: usize,
+    /// sum of the votes: 1 for an exact match, at most `FUZZY_WEIGHT` for a fuzzy match
+    pub score: f32,
+}
+
+/// A virtual database over the databases of the reference fonts.
+///
+/// The databases are shared with the cache of `FontDb`, entries are numbered font by font.
+pub struct GlobalIndex {
+    params: BuildParams,
+    fonts: Vec<String>,
+    dbs: Vec<Arc<ReferenceDb>>,
+    /// index of the first entry of each font, followed by the total number of entries
+    offsets: Vec<usize>,
+    fuzzy_threshold: Option<f32>,
+    cyclic_fuzzy: bool,
+    fuzzy_index: OnceLock<FuzzyIndex>,
+    cache_key: OnceLock<Option<u64>>,
+}
+impl GlobalIndex {
+    pub fn new(params: BuildParams) -> Self {
+        GlobalIndex {
+            params,
+            fonts: vec![],
+            dbs: vec![],
+            offsets: vec![0],
+            fuzzy_threshold: default_fuzzy_threshold(),
+            cyclic_fuzzy: false,
+            fuzzy_index: OnceLock::new(),
+            cache_key: OnceLock::new(),
+        }
+    }
+    /// Add the reference font `ps_name`.
+    ///
+    /// Returns false if `db` was built with different parameters than the index.
+    pub fn add(&mut self, ps_name: &str, db: Arc<ReferenceDb>) -> bool {
+        if *db.params() != self.params {
+            return false;
+        }
+        self.offsets.push(self.num_entries() + db.len());
+        self.fonts.push(ps_name.into());
+        self.dbs.push(db);
+        self.fuzzy_index = OnceLock::new();
+        self.cache_key = OnceLock::new();
+        true
+    }
+    /// see `ShapeDb::set_fuzzy_threshold`
+    pub fn set_fuzzy_threshold(&mut self, threshold: Option<f32>) {
+        self.fuzzy_threshold = threshold;
+        self.cache_key = OnceLock::new();
+    }
+    /// see `ShapeDb::set_cyclic_fuzzy`
+    pub fn set_cyclic_fuzzy(&mut self, cyclic: bool) {
+        self.cyclic_fuzzy = cyclic;
+        self.cache_key = OnceLock::new();
+    }
+    /// Identifies the results of lookups in the index, see `ShapeDb::cache_key`.
+    ///
+    /// Depends on the fonts in their order, so `None` if one of them has no key.
+    pub fn cache_key(&self) -> Option<u64> {
+        *self.cache_key.get_or_init(|| {
+            let mut h = Fnv1a::default();
+            for db in self.dbs.iter() {
+                h.write(&db.cache_key()?.to_le_bytes());
+            }
+            Some(cache_key(INDEX_VERSION, h.finish(), self.fuzzy_threshold, self.cyclic_fuzzy))
+        })
+    }
+    /// PostScript names of the reference fonts
+    pub fn fonts(&self) -> &[String] {
+        &self.fonts
+    }
+    pub fn contains(&self, ps_name: &str) -> bool {
+        self.fonts.iter().any(|f| f == ps_name)
+    }
+    pub fn font_name(&self, label: &GlobalLabel) -> &str {
+        &self.fonts[label.font as usize]
+    }
+    /// the font of entry `idx` and the index of the entry in its database
+    fn locate(&self, idx: usize) -> (usize, usize) {
+        let font = self.offsets.partition_point(|&start| start <= idx) - 1;
+        (font, idx - self.offsets[font])
+    }
+    /// the label of the glyph matching `outline` and its reference font, see `ShapeDb::get`
+    pub fn get(&self, outline: &Outline, font_matrix: Transform2F, report: Option<&mut String>) -> Option<GlobalLabel> {
+        find(self, outline, font_matrix, report).map(|idx| self.global_label(idx))
+    }
+    /// like `get`, answering from `memo` if possible
+    pub fn get_or_lookup(&self, memo: &ResultCache, outline: &Outline, font_matrix: Transform2F) -> Option<GlobalLabel> {
+        memo.find(self, self.cache_key(), outline, font_matrix).map(|idx| self.global_label(idx))
+    }
+    fn global_label(&self, idx: usize) -> GlobalLabel {
+        let (font, _) = self.locate(idx);
+        GlobalLabel { font: font as u32, label: self.label(idx).into() }
+    }
+    /// match every glyph of `font` against all reference fonts
+    pub fn check_font(&self, font: &(dyn Font + Sync + Send)) -> Result<FontMatch> {
+        self.check_glyphs(font, &|_| true)
+    }
+    /// match the glyphs of `font` for which `filter` returns true against all reference fonts
+    pub fn check_glyphs(&self, font: &(dyn Font + Sync + Send), filter: &dyn Fn(GlyphId) -> bool) -> Result<FontMatch> {
+        Ok(self.match_font(None, font, filter))
+    }
+    /// like `check_glyphs`, remembering the results in `memo`
+    pub(crate) fn match_font(&self, memo: Option<&ResultCache>, font: &(dyn Font + Sync + Send), filter: &dyn Fn(GlyphId) -> bool) -> FontMatch {
+        let labels = match memo {
+            Some(memo) => match_glyphs(&|outline, font_matrix| self.get_or_lookup(memo, outline, font_matrix), font, filter),
+            None => match_glyphs(&|outline, font_matrix| self.get(outline, font_matrix, None), font, filter),
+        };
+
+        let mut counts = vec![0usize; self.fonts.len()];
+        let glyphs: HashMap<_, _> = labels.into_iter().map(|(gid, l)| {
+            counts[l.font as usize] += 1;
+            (gid, l.label)
+        }).collect();
+
+        let mut sources: Vec<_> = counts.into_iter().enumerate()
+            .filter(|&(_, n)| n > 0)
+            .map(|(i, n)| (self.fonts[i].clone(), n))
+            .collect();
+        sources.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        FontMatch { glyphs, sources, resolved: None }
+    }
+    /// the weight of every reference font that has a glyph matching `outline`, see `identify`
+    fn votes(&self, outline: &Outline, font_matrix: Transform2F) -> HashMap<usize, f32> {
+        let (outline, _) = prepare(&self.params, outline, font_matrix);
+        let test_sets: Vec<_> = outline.contours().iter().map(points_set).collect();
+
+        let mut votes: HashMap<usize, f32> = HashMap::new();
+        let (candidates, _) = candidates(self, &outline);
+        for (idx, _) in candidates {
+            if self.num_contours(idx) != test_sets.len() {
+                continue;
+            }
+            if match_contours(self, &test_sets, idx).iter().all(|&b| b) {
+                votes.insert(self.locate(idx).0, 1.);
+            }
+        }
+        if !votes.is_empty() {
+            return votes;
+        }
+
+        let Some(threshold) = self.fuzzy_threshold else { return votes };
+        let test_contours: Vec<_> = outline.contours().iter().map(canonical_points).collect();
+        for idx in self.fuzzy_index().candidates(&test_contours, threshold) {
+            let Some(dist) = fuzzy_distance(self, &test_contours, idx) else { continue };
+            if dist > threshold {
+                continue;
+            }
+            let vote = FUZZY_WEIGHT * (1.0 - dist / threshold.max(f32::EPSILON));
+            let v = votes.entry(self.locate(idx).0).or_default();
+            *v = v.max(vote);
+        }
+        votes
+    }
+    /// Which reference fonts is `font` a subset of?
+    ///
+    /// Every glyph votes for all reference fonts that contain an exactly matching glyph.
+    /// Only glyphs without an exact match anywhere use the fuzzy matcher, and their votes weigh less.
+    /// Returns the candidates with at least one vote, highest score first.
+    pub fn identify(&self, font: &(dyn Font + Sync + Send)) -> Vec<FontCandidate> {
+        let n = self.fonts.len();
+        let mut matched = vec![0usize; n];
+        let mut unique = vec![0usize; n];
+        let mut score = vec![0f32; n];
+        let mut tested = 0;
+
+        for i in 0 .. font.num_glyphs() {
+            let Some(g) = font.glyph(GlyphId(i)) else { continue };
+            if g.path.is_empty() {
+                continue;
+            }
+            tested += 1;
+
+            let votes = self.votes(&g.path, font.font_matrix());
+            for (&f, &v) in votes.iter() {
+                matched[f] += 1;
+                score[f] += v;
+                if votes.len() == 1 {
+                    unique[f] += 1;
+                }
+            }
+        }
+
+        let mut candidates: Vec<_> = (0 .. n).filter(|&i| matched[i] > 0).map(|i| FontCandidate {
+            ps_name: self.fonts[i].clone(),
+            matched: matched[i],
+            unique: unique[i],
+            tested,
+            coverage: matched[i] as f32 / tested.max(1) as f32,
+            reference_glyphs: self.dbs[i].len(),
+            score: score[i],
+        }).collect();
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score).then(b.matched.cmp(&a.matched)).then(a.ps_name.cmp(&b.ps_name)));
+        candidates
+    }
+}
+
+impl ShapeData for GlobalIndex {
+    type Label<'a> = &'a str;
+
+    fn params(&self) -> &BuildParams {
+        &self.params
+    }
+    fn fuzzy_threshold(&self) -> Option<f32> {
+        self.fuzzy_threshold
+    }
+    fn cyclic_fuzzy(&self) -> bool {
+        self.cyclic_fuzzy
+    }
+    fn num_entries(&self) -> usize {
+        self.offsets[self.fonts.len()]
+    }
+    fn label(&self, idx: usize) -> &str {
+        let (font, idx) = self.locate(idx);
+        self.dbs[font].label(idx)
+    }
+    fn query_points(&self, key: PointKey, f: &mut dyn FnMut(usize)) {
+        for (db, &start) in self.dbs.iter().zip(self.offsets.iter()) {
+            db.query_points(key, &mut |idx| f(start + idx));
+        }
+    }
+    fn num_contours(&self, idx: usize) -> usize {
+        let (font, idx) = self.locate(idx);
+        self.dbs[font].num_contours(idx)
+    }
+    fn contour_matches(&self, idx: usize, contour: usize, test: &HashSet<PointKey>) -> bool {
+        let (font, idx) = self.locate(idx);
+        self.dbs[font].contour_matches(idx, contour, test)
+    }
+    fn fuzzy_outline(&self, idx: usize) -> Vec<Vec<Vector2F>> {
+        let (font, idx) = self.locate(idx);
+        self.dbs[font].fuzzy_outline(idx)
+    }
+    fn fuzzy_index(&self) -> &FuzzyIndex {
+        self.fuzzy_index.get_or_init(|| FuzzyIndex::build(self))
+    }
+}
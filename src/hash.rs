@@ -0,0 +1,58 @@
+use std::hash::Hasher;
+
+use pathfinder_content::outline::Outline;
+
+use crate::canonical::canonical_points;
+use crate::index::point_key;
+
+/// 64 bit FNV-1a hash.
+///
+/// Unlike `DefaultHasher` its output is stable across Rust versions and platforms,
+/// so it can be stored in files.
+#[derive(Clone, Copy)]
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub fn hash_bytes(data: &[u8]) -> u64 {
+    let mut h = Fnv1a::default();
+    h.write(data);
+    h.finish()
+}
+
+/// Hash of the rounded points of `outline`, independent of the start point,
+/// direction and order of its contours.
+pub fn outline_hash(outline: &Outline) -> u64 {
+    let mut contours: Vec<u64> = outline.contours().iter().map(|c| {
+        let mut h = Fnv1a::default();
+        for p in canonical_points(c) {
+            let (x, y) = point_key(p);
+            h.write(&x.to_le_bytes());
+            h.write(&y.to_le_bytes());
+        }
+        h.finish()
+    }).collect();
+    contours.sort_unstable();
+
+    let mut h = Fnv1a::default();
+    h.write(&(contours.len() as u64).to_le_bytes());
+    for c in contours {
+        h.write(&c.to_le_bytes());
+    }
+    h.finish()
+}
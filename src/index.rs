@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+
+use pathfinder_geometry::vector::Vector2F;
+use serde::{Deserialize, Serialize};
+
+/// signed, so points left of or below the origin keep their position
+pub type PointKey = (i32, i32);
+
+pub fn point_key(p: Vector2F) -> PointKey {
+    (p.x().round() as i32, p.y().round() as i32)
+}
+
+/// Spatial index of points, bucketed into square cells.
+///
+/// Cells are as large as the tolerance, so all points within the tolerance
+/// of a given point are found in the cell of that point and its direct neighbours.
+#[derive(Serialize, Deserialize)]
+pub struct PointIndex {
+    cell_size: i32,
+    radius: i32,
+    cells: HashMap<PointKey, Vec<usize>>,
+}
+impl PointIndex {
+    pub fn new(tolerance: f32) -> Self {
+        let tolerance = tolerance.max(0.).ceil() as i32;
+        PointIndex {
+            cell_size: tolerance.max(1),
+            radius: if tolerance > 0 { 1 } else { 0 },
+            cells: HashMap::new(),
+        }
+    }
+    fn cell(&self, key: PointKey) -> PointKey {
+        cell_of(key, self.cell_size)
+    }
+    pub(crate) fn cell_size(&self) -> i32 {
+        self.cell_size
+    }
+    pub(crate) fn radius(&self) -> i32 {
+        self.radius
+    }
+    /// all non-empty cells and the entries in them
+    pub(crate) fn cells(&self) -> impl Iterator<Item=(PointKey, &[usize])> {
+        self.cells.iter().map(|(&cell, list)| (cell, list.as_slice()))
+    }
+    /// record that entry `idx` has a point at `key`
+    ///
+    /// Entries have to be inserted in increasing order.
+    pub fn insert(&mut self, key: PointKey, idx: usize) {
+        let cell = self.cell(key);
+        let list = self.cells.entry(cell).or_default();
+        if list.last() != Some(&idx) {
+            list.push(idx);
+        }
+    }
+    /// calls `f` once for every entry that has a point in the neighbourhood of `key`
+    pub fn query(&self, key: PointKey, f: impl FnMut(usize)) {
+        query_cells(self.cell_size, self.radius, key, |cell| self.cells.get(&cell).map(|list| list.iter().copied()), f)
+    }
+}
+
+pub(crate) fn cell_of(key: PointKey, cell_size: i32) -> PointKey {
+    (key.0.div_euclid(cell_size), key.1.div_euclid(cell_size))
+}
+
+/// Calls `f` once for every entry in the cells within `radius` of the cell of `key`.
+///
+/// `cell` returns the entries of a cell.
+pub(crate) fn query_cells<L: Iterator<Item=usize>>(cell_size: i32, radius: i32, key: PointKey, cell: impl Fn(PointKey) -> Option<L>, mut f: impl FnMut(usize)) {
+    let (cx, cy) = cell_of(key, cell_size);
+    let r = radius;
+    if r == 0 {
+        if let Some(list) = cell((cx, cy)) {
+            list.for_each(f);
+        }
+        return;
+    }
+    let mut seen = HashSet::new();
+    for x in cx - r ..= cx + r {
+        for y in cy - r ..= cy + r {
+            if let Some(list) = cell((x, y)) {
+                for idx in list {
+                    if seen.insert(idx) {
+                        f(idx);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn within(a: PointKey, b: PointKey, tolerance: f32) -> bool {
+    let dx = (a.0 as f32 - b.0 as f32).abs();
+    let dy = (a.1 as f32 - b.1 as f32).abs();
+    dx <= tolerance && dy <= tolerance
+}
+
+/// Is there a point within `tolerance` of `p`, given `contains` for the lookup of a single point?
+///
+/// Probes the keys around `p`, or scans `points` if there are fewer of them than keys to probe.
+fn has_near<'a>(p: PointKey, tolerance: f32, contains: impl Fn(&PointKey) -> bool, points: impl ExactSizeIterator<Item=&'a PointKey>) -> bool {
+    if contains(&p) {
+        return true;
+    }
+    let r = tolerance.floor().max(0.) as i32;
+    let side = 2 * r as u64 + 1;
+    if side.saturating_mul(side) > points.len() as u64 {
+        return points.into_iter().any(|&q| within(p, q, tolerance));
+    }
+    (p.0.saturating_sub(r) ..= p.0.saturating_add(r))
+        .any(|x| (p.1.saturating_sub(r) ..= p.1.saturating_add(r)).any(|y| contains(&(x, y))))
+}
+
+/// compares two point sets, allowing each point to be off by `tolerance` in each direction
+pub fn sets_match(a: &HashSet<PointKey>, b: &HashSet<PointKey>, tolerance: f32) -> bool {
+    if tolerance <= 0. {
+        return a == b;
+    }
+    a.iter().all(|&p| has_near(p, tolerance, |q| b.contains(q), b.iter())) &&
+    b.iter().all(|&q| has_near(q, tolerance, |p| a.contains(p), a.iter()))
+}
+
+/// like `sets_match`, with `b` given as a sorted list of distinct points
+pub fn sets_match_sorted(a: &HashSet<PointKey>, b: &[PointKey], tolerance: f32) -> bool {
+    if tolerance <= 0. {
+        return a.len() == b.len() && b.iter().all(|q| a.contains(q));
+    }
+    a.iter().all(|&p| has_near(p, tolerance, |q| b.binary_search(q).is_ok(), b.iter())) &&
+    b.iter().all(|&q| has_near(q, tolerance, |p| a.contains(p), a.iter()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the quadratic comparison `sets_match` replaces
+    fn scan(a: &HashSet<PointKey>, b: &HashSet<PointKey>, tolerance: f32) -> bool {
+        a.iter().all(|&p| b.iter().any(|&q| within(p, q, tolerance))) &&
+        b.iter().all(|&q| a.iter().any(|&p| within(p, q, tolerance)))
+    }
+
+    #[test]
+    fn tolerant_match() {
+        let a: HashSet<PointKey> = [(0, 0), (10, 0), (10, 10), (-5, -5)].into_iter().collect();
+        let b: HashSet<PointKey> = [(1, -1), (10, 2), (9, 10), (-6, -4)].into_iter().collect();
+        assert!(!sets_match(&a, &b, 0.));
+        assert!(!sets_match(&a, &b, 1.5));
+        assert!(sets_match(&a, &b, 2.));
+        let mut sorted: Vec<_> = b.iter().copied().collect();
+        sorted.sort();
+        assert!(sets_match_sorted(&a, &sorted, 2.));
+        assert!(!sets_match_sorted(&a, &sorted, 1.));
+    }
+
+    #[test]
+    fn probing_agrees_with_scan() {
+        // a large set makes `has_near` probe instead of scanning
+        let a: HashSet<PointKey> = (0 .. 200).map(|i| (i * 7 % 97 - 40, i * 13 % 89 - 40)).collect();
+        for (shift, tolerance) in [((1, 0), 1.), ((2, -2), 1.9), ((2, -2), 2.), ((3, 1), 3.5), ((-1, 0), 0.5)] {
+            let b: HashSet<PointKey> = a.iter().map(|&(x, y)| (x + shift.0, y + shift.1)).collect();
+            let mut sorted: Vec<_> = b.iter().copied().collect();
+            sorted.sort();
+            let expected = scan(&a, &b, tolerance);
+            assert_eq!(sets_match(&a, &b, tolerance), expected, "{shift:?} {tolerance}");
+            assert_eq!(sets_match_sorted(&a, &sorted, tolerance), expected, "{shift:?} {tolerance}");
+        }
+    }
+}
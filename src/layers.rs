@@ -0,0 +1,114 @@
+//! Labels from the embedded font itself.
+//!
+//! Glyph names and cmap entries of the embedded font are used where they give a usable label,
+//! only the remaining glyphs are matched by shape (see `FontDb::check_font_layered`).
+
+use std::collections::HashMap;
+
+use font::{type1::Type1Font, CffFont, Font, GlyphId, OpenTypeFont, TrueTypeFont};
+use istring::SmallString;
+
+use crate::aliases::Resolved;
+use crate::validate::Validation;
+use crate::{glyph_name_to_unicode, use_cmap, use_encoding};
+
+/// which layer produced a label
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LabelSource {
+    /// the name of the glyph in the embedded font
+    GlyphName,
+    /// the cmap of the embedded font
+    CMap,
+    /// shape matching against the reference fonts
+    Shape,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub text: SmallString,
+    pub source: LabelSource,
+}
+
+/// result of `FontDb::check_font_layered`
+#[derive(Clone, Debug, Default)]
+pub struct LayeredMatch {
+    pub labels: HashMap<GlyphId, Label>,
+    /// reference fonts that labeled glyphs by shape and how many, most first
+    pub sources: Vec<(String, usize)>,
+    /// how the requested name was mapped to a reference font, `None` if all fonts were searched
+    pub resolved: Option<Resolved>,
+    /// sources whose labels disagreed with the shape matches and were not used
+    pub dropped: Vec<(LabelSource, Validation)>,
+}
+impl LayeredMatch {
+    /// the labels without their source
+    pub fn glyphs(&self) -> HashMap<GlyphId, SmallString> {
+        self.labels.iter().map(|(&gid, l)| (gid, l.text.clone())).collect()
+    }
+    /// number of glyphs labeled by `source`
+    pub fn count(&self, source: LabelSource) -> usize {
+        self.labels.values().filter(|l| l.source == source).count()
+    }
+}
+
+/// Labels in the private use areas or with control characters are what subsetting tools
+/// produce for symbolic fonts, they do not say anything about the glyph.
+fn is_usable(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| !c.is_control() && !matches!(c as u32, 0xE000 ..= 0xF8FF | 0xF0000 ..))
+}
+
+/// Labels from the glyph names and the cmap of `font`, for each source in order of preference.
+///
+/// Glyph names come from the charset of CFF fonts, the `post` table of TrueType fonts
+/// and the encoding of Type1 fonts. A glyph may have several labels. Unusable labels are left out.
+pub fn source_labels(font: &(dyn Font + Sync + Send)) -> Vec<(LabelSource, Vec<(GlyphId, SmallString)>)> {
+    let mut sources = vec![];
+    let mut add = |list: Vec<(GlyphId, SmallString)>, source: LabelSource| {
+        let list: Vec<_> = list.into_iter().filter(|(_, text)| is_usable(text)).collect();
+        if !list.is_empty() {
+            sources.push((source, list));
+        }
+    };
+    let names = |map: &HashMap<String, u16>| -> Vec<(GlyphId, SmallString)> {
+        map.iter().filter_map(|(name, &id)| Some((GlyphId(id as u32), glyph_name_to_unicode(name)?))).collect()
+    };
+
+    if let Some(cff) = font.downcast_ref::<CffFont>() {
+        if cff.name_map.is_empty() {
+            add(use_encoding(font).unwrap_or_default(), LabelSource::GlyphName);
+        } else {
+            add(names(&cff.name_map), LabelSource::GlyphName);
+        }
+    } else if font.downcast_ref::<Type1Font>().is_some() {
+        add(use_encoding(font).unwrap_or_default(), LabelSource::GlyphName);
+    } else if let Some(otf) = font.downcast_ref::<OpenTypeFont>() {
+        add(names(&otf.name_map), LabelSource::GlyphName);
+        if let Some(ref cmap) = otf.cmap {
+            add(use_cmap(cmap), LabelSource::CMap);
+        }
+    } else if let Some(ttf) = font.downcast_ref::<TrueTypeFont>() {
+        add(names(&ttf.name_map), LabelSource::GlyphName);
+        if let Some(ref cmap) = ttf.cmap {
+            add(use_cmap(cmap), LabelSource::CMap);
+        }
+    }
+    sources
+}
+
+/// Merge the labels of `sources`, the first source that labels a glyph wins.
+pub fn merge_labels<'a>(sources: impl IntoIterator<Item=&'a (LabelSource, Vec<(GlyphId, SmallString)>)>) -> HashMap<GlyphId, Label> {
+    let mut labels = HashMap::new();
+    for (source, list) in sources {
+        for (gid, text) in list {
+            labels.entry(*gid).or_insert_with(|| Label { text: text.clone(), source: *source });
+        }
+    }
+    labels
+}
+
+/// Labels from the glyph names and the cmap of `font`, see `source_labels`.
+///
+/// Glyph names are preferred over the cmap.
+pub fn font_labels(font: &(dyn Font + Sync + Send)) -> HashMap<GlyphId, Label> {
+    merge_labels(&source_labels(font))
+}
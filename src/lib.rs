@@ -1,101 +1,316 @@
-use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, fmt::Display, sync::{RwLock, Arc}};
+use std::{collections::{BTreeMap, HashMap, HashSet}, path::{Path, PathBuf}, fmt::Display, sync::{Arc, OnceLock}};
 
-use font::{TrueTypeFont, CffFont, OpenTypeFont, opentype::cmap::CMap, GlyphId, Glyph, Font};
+use font::{TrueTypeFont, CffFont, OpenTypeFont, type1::Type1Font, opentype::cmap::CMap, GlyphId, Font};
 use istring::SmallString;
-use itertools::Itertools;
-use pathfinder_content::outline::{Contour, Outline};
+use pathfinder_content::outline::Outline;
 use pathfinder_geometry::vector::Vector2F;
 use pdf_encoding::glyphname_to_unicode;
 use serde::{Deserialize, Serialize};
 
-use crate::frechet::frechet_distance;
+use pathfinder_geometry::transform2d::Transform2F;
 
+pub use crate::cache::CachePolicy;
+pub use crate::error::{Error, Result};
+
+use crate::aliases::{AliasConfig, Resolution, Resolved, ALIAS_FILE};
+use crate::cache::{Cache, Slot};
+use crate::canonical::canonical_points;
+use crate::global::{FontCandidate, GlobalIndex};
+use crate::hash::{hash_bytes, outline_hash};
+use crate::index::{sets_match, PointIndex, PointKey};
+use crate::layers::{merge_labels, source_labels, Label, LabelSource, LayeredMatch};
+use crate::lookup::{find, points_set, prepare, rank, FuzzyIndex, ShapeData};
+use crate::mapped::{is_mapped, is_mapped_file, parse_mapped_header, MappedDb};
+use crate::memo::{cache_key, ResultCache};
+use crate::names::{lookup_names, normalize_ps_name};
+use crate::normalize::{transform_to_array, Normalization};
+use crate::simple::label_codes;
+use crate::store::{replace_file, write_pack, Catalog, CatalogEntry, Pack, CATALOG_FILE, TMP_SUFFIX};
+use crate::validate::{validate_labels, FontValidation, DEFAULT_THRESHOLD};
+
+pub mod aliases;
+pub mod assignment;
+pub mod batch;
+mod cache;
+pub mod canonical;
+pub mod error;
+pub mod flatten;
+pub mod format;
 pub mod frechet;
+pub mod global;
+pub mod hash;
+pub mod index;
+pub mod layers;
+pub mod lookup;
+pub mod mapped;
+pub mod memo;
+pub mod names;
+pub mod normalize;
+pub mod simple;
+pub mod store;
+pub mod tounicode;
+pub mod validate;
+
+#[cfg(test)]
+pub(crate) mod test_util;
 
 #[derive(Serialize, Deserialize)]
 struct Entry<I> {
-    contour_sets: Vec<HashSet<(u16, u16)>>,
-   // outline: Outline,
+    contour_sets: Vec<HashSet<PointKey>>,
+    /// normalized points of each contour, used for fuzzy matching
+    outline: Vec<Vec<(f32, f32)>>,
+    /// transform that was applied to the outline before it was indexed
+    transform: [f32; 6],
     data: I,
 }
  
+/// parameters that affect how a `ShapeDb` is built
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct BuildParams {
+    pub normalization: Normalization,
+    /// how far (in normalized units) points may be apart and still be considered equal
+    pub tolerance: f32,
+    /// Resample contours into on-curve points this far apart (in normalized units)
+    /// instead of using the points stored in the font.
+    pub sample_spacing: Option<f32>,
+}
+impl Default for BuildParams {
+    fn default() -> Self {
+        BuildParams {
+            normalization: Normalization::default(),
+            // resampled points of quadratic and cubic sources differ slightly
+            tolerance: 2.0,
+            sample_spacing: Some(20.0),
+        }
+    }
+}
+
+/// how a `RankedMatch` was found
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MatchKind {
+    /// every contour matched
+    Exact,
+    /// some points or contours matched
+    Partial,
+    /// found by the frechet distance matcher
+    Fuzzy,
+}
+
+/// a result of `ShapeDb::get_ranked`
+#[derive(Debug)]
+pub struct RankedMatch<'a, I> {
+    pub data: &'a I,
+    pub kind: MatchKind,
+    /// between 0 and 1, higher is better
+    pub score: f32,
+    /// number of distinct points shared with the query
+    pub votes: usize,
+    pub matched_contours: usize,
+    /// difference in score to the next result (or to 0 for the last one)
+    pub margin: f32,
+}
+
+fn default_fuzzy_threshold() -> Option<f32> {
+    Some(20.)
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ShapeDb<I> {
+    params: BuildParams,
     entries: Vec<Entry<I>>,
-    points: HashMap<(u16, u16), Vec<usize>>,
+    points: PointIndex,
+    #[serde(skip, default = "default_fuzzy_threshold")]
+    fuzzy_threshold: Option<f32>,
+    #[serde(skip)]
+    cyclic_fuzzy: bool,
+    /// hash of the encoded database this was loaded from
+    #[serde(skip)]
+    content_hash: Option<u64>,
+    #[serde(skip)]
+    fuzzy_index: OnceLock<FuzzyIndex>,
+}
+impl<I> Default for ShapeDb<I> {
+    fn default() -> Self {
+        ShapeDb::new()
+    }
 }
 impl<I> ShapeDb<I> {
     pub fn new() -> Self {
+        ShapeDb::with_params(BuildParams::default())
+    }
+    pub fn with_normalization(normalization: Normalization) -> Self {
+        ShapeDb::with_params(BuildParams { normalization, ..BuildParams::default() })
+    }
+    pub fn with_params(params: BuildParams) -> Self {
         ShapeDb {
+            params,
             entries: vec![],
-            points: HashMap::new(),
+            points: PointIndex::new(params.tolerance),
+            fuzzy_threshold: default_fuzzy_threshold(),
+            cyclic_fuzzy: false,
+            content_hash: None,
+            fuzzy_index: OnceLock::new(),
         }
     }
+    /// Set the maximum average frechet distance per contour (in normalized units)
+    /// of the fuzzy matcher that is used when no exact match is found.
+    /// `None` disables fuzzy matching.
+    pub fn set_fuzzy_threshold(&mut self, threshold: Option<f32>) {
+        self.fuzzy_threshold = threshold;
+    }
+    pub fn fuzzy_threshold(&self) -> Option<f32> {
+        self.fuzzy_threshold
+    }
+    /// Let the fuzzy matcher minimize the distance over all start points and directions of a contour
+    /// instead of relying on the canonical form. Much slower, but robust against unstable start points.
+    pub fn set_cyclic_fuzzy(&mut self, cyclic: bool) {
+        self.cyclic_fuzzy = cyclic;
+    }
+    pub fn params(&self) -> &BuildParams {
+        &self.params
+    }
+    pub fn normalization(&self) -> Normalization {
+        self.params.normalization
+    }
+    /// number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Identifies the results of lookups in this database, `None` if it was not loaded from a file.
+    ///
+    /// Depends on the content of the file and the fuzzy matching settings.
+    pub fn cache_key(&self) -> Option<u64> {
+        Some(cache_key(format::VERSION, self.content_hash?, self.fuzzy_threshold, self.cyclic_fuzzy))
+    }
+    /// Hash of `outline` after normalization and resampling.
+    ///
+    /// Points are rounded like the point keys and contours are compared in canonical form,
+    /// so the hash does not depend on the start point, direction or order of the contours.
+    pub fn outline_hash(&self, outline: &Outline, font_matrix: Transform2F) -> u64 {
+        let (outline, _) = prepare(&self.params, outline, font_matrix);
+        outline_hash(&outline)
+    }
+    /// Add all entries of `other`, converting their values with `f`.
+    ///
+    /// Returns false and adds nothing if `other` was built with different parameters.
+    pub fn extend_from<J>(&mut self, other: &ShapeDb<J>, mut f: impl FnMut(&J) -> I) -> bool {
+        if other.params != self.params {
+            return false;
+        }
+        for e in other.entries.iter() {
+            self.push_entry(Entry {
+                contour_sets: e.contour_sets.clone(),
+                outline: e.outline.clone(),
+                transform: e.transform,
+                data: f(&e.data),
+            });
+        }
+        true
+    }
+    fn push_entry(&mut self, entry: Entry<I>) {
+        let idx = self.entries.len();
+        for set in entry.contour_sets.iter() {
+            for &key in set.iter() {
+                self.points.insert(key, idx);
+            }
+        }
+        self.entries.push(entry);
+        self.fuzzy_index = OnceLock::new();
+    }
+}
+
+fn parse_font(path: &Path, data: &[u8]) -> Result<Box<dyn Font + Sync + Send>> {
+    font::parse(data).map_err(|e| Error::FontParse { path: path.into(), message: format!("{e:?}") })
 }
 
-fn add_font(db_dir: &Path, font_file: &Path) {
-    let data = std::fs::read(&font_file).unwrap();
-    let font = font::parse(&data).unwrap();
-    let ps_name = match dbg!(&font.name().postscript_name) {
-        Some(ref n) => n,
+fn add_font(db_dir: &Path, font_file: &Path) -> Result<CatalogEntry> {
+    let data = std::fs::read(font_file)?;
+    let font = parse_font(font_file, &data)?;
+    let file_stem = font_file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ps_name = match font.name().postscript_name {
+        Some(ref n) => n.as_str(),
         None => {
-            println!("no postscript name");
-            font_file.file_stem().unwrap().to_str().unwrap()
+            log::info!("{font_file:?} has no PostScript name, using {file_stem}");
+            file_stem.as_str()
         }
     };
     let use_name = font_file.extension().map(|s| s == "name").unwrap_or(false);
 
     let mut db = ShapeDb::new();
 
-    let label_file = Path::new("unicode").join(font_file.file_stem().unwrap()).with_extension("json");
-    let list = if label_file.exists() {
-        println!("loading patch");
-        let list: UnicodeList = serde_json::from_slice(&std::fs::read(&label_file).unwrap()).unwrap();
-        Some(list.into_iter().map(|e| (GlyphId(e.gid), e.unicode.iter().flat_map(|&n| std::char::from_u32(n)).collect())).collect())
+    let label_file = Path::new("unicode").join(&file_stem).with_extension("json");
+    let list: Vec<(GlyphId, SmallString)> = if label_file.exists() {
+        log::debug!("using labels from {label_file:?}");
+        let list: UnicodeList = serde_json::from_slice(&std::fs::read(&label_file)?).map_err(Error::LabelFile)?;
+        list.into_iter().map(|e| (GlyphId(e.gid), e.unicode.iter().flat_map(|&n| std::char::from_u32(n)).collect())).collect()
     } else {
-        font_uni_list(&*font, use_name)
+        font_uni_list(&*font, use_name)?
     };
-    if let Some(list) = list {
 
-        for (gid, s) in list {
-            let g = font.glyph(gid).unwrap();
-            
-            db.add_outline(&g.path, s);
-        }
-
-        let db_data = postcard::to_allocvec(&db).unwrap();
-        std::fs::write(db_dir.join(ps_name), &db_data).unwrap();
+    for (gid, s) in list {
+        let Some(g) = font.glyph(gid) else {
+            log::warn!("{font_file:?} has no glyph {}", gid.0);
+            continue;
+        };
+        
+        db.add_outline(&g.path, font.font_matrix(), s);
     }
+
+    let source_hash = hash_bytes(&data);
+    let db_data = db.to_bytes(source_hash)?;
+    replace_file(&db_dir.join(ps_name), &db_data)?;
+
+    let name = font.name();
+    Ok(CatalogEntry {
+        ps_name: ps_name.into(),
+        family: name.family.clone(),
+        style: name.subfamily.clone(),
+        version: name.version.clone(),
+        glyph_count: db.len(),
+        source_path: font_file.to_string_lossy().into_owned(),
+        source_hash,
+    })
 }
 
-pub fn init(db_dir: &Path) {
-    for e in std::fs::read_dir("fonts").unwrap().filter_map(|r| r.ok()) {
+/// Build databases for all fonts in the `fonts` directory.
+///
+/// Fonts that fail are reported and skipped.
+pub fn init(db_dir: &Path) -> Result<()> {
+    let mut catalog = Catalog::load_dir(db_dir)?;
+    for e in std::fs::read_dir("fonts")?.filter_map(|r| r.ok()) {
         let path = e.path();
-        println!("{path:?}");
-        add_font(db_dir, &path);
+        log::info!("adding {path:?}");
+        match add_font(db_dir, &path) {
+            Ok(entry) => catalog.insert(entry),
+            Err(e) => log::warn!("failed to add {path:?}: {e}"),
+        }
     }
+    catalog.save_dir(db_dir)
+}
+
+pub fn font_uni_list(font: &(dyn Font + Sync + Send), use_name: bool) -> Result<Vec<(GlyphId, SmallString)>> {
+    font_uni_list_opt(font, use_name).ok_or_else(|| Error::MissingLabels(font.name().postscript_name.clone().unwrap_or_default()))
 }
 
-pub fn font_uni_list(font: &(dyn Font + Sync + Send), use_name: bool) -> Option<Vec<(GlyphId, SmallString)>> {
+fn font_uni_list_opt(font: &(dyn Font + Sync + Send), use_name: bool) -> Option<Vec<(GlyphId, SmallString)>> {
     if let Some(ttf) = font.downcast_ref::<TrueTypeFont>() {
-        println!("TTF");
-        if let Some(ref cmap) = ttf.cmap {
-            Some(use_cmap(cmap))
+        ttf.cmap.as_ref().map(use_cmap)
+    } else if let Some(cff) = font.downcast_ref::<CffFont>() {
+        if !cff.name_map.is_empty() {
+            Some(use_name_map(&cff.name_map))
         } else {
-            None
+            use_encoding(font)
         }
-    } else if let Some(cff) = font.downcast_ref::<CffFont>() {
-        println!("CFF");
-        None
+    } else if font.downcast_ref::<Type1Font>().is_some() {
+        use_encoding(font)
     } else if let Some(otf) = font.downcast_ref::<OpenTypeFont>() {
-        println!("OTF");
-        if use_name && otf.name_map.len() > 0 {
+        if use_name && !otf.name_map.is_empty() {
             Some(use_name_map(&otf.name_map))
-        }
-        else if let Some(ref cmap) = otf.cmap {
-            Some(use_cmap(cmap))
         } else {
-            None
+            otf.cmap.as_ref().map(use_cmap)
         }
     } else {
         None
@@ -114,147 +329,153 @@ fn use_cmap(cmap: &CMap) -> Vec<(GlyphId, SmallString)> {
 fn use_name_map(map: &HashMap<String, u16>) -> Vec<(GlyphId, SmallString)> {
     let mut v = vec![];
     for (name, &id) in map.iter() {
-        if let Some(s) = glyphname_to_unicode(&name) {
-            v.push((GlyphId(id as u32), s.into()));
-        } else if let Some(uni) = name.strip_prefix("uni").and_then(|hex| u32::from_str_radix(hex, 16).ok()).and_then(std::char::from_u32) {
-            v.push((GlyphId(id as u32), uni.into()));
-        } else {
-            println!("not found: {name}");
+        if let Some(s) = glyph_name_to_unicode(name) {
+            v.push((GlyphId(id as u32), s));
         }
     }
     v
 }
-
-impl<I: Display + PartialEq> ShapeDb<I> {
-    pub fn add_outline(&mut self, outline: &Outline, value: I) {
-        let val_idx = self.entries.len();
-        let mut points_seen = HashSet::new();
-        for c in outline.contours().iter() {
-            for &p in c.points().iter() {
-                let key = (p.x() as u16, p.y() as u16);
-                if points_seen.insert(key) {
-                    self.points.entry(key).or_default().push(val_idx);
-                }
-            }
+/// labels from the built-in encoding of the font (Type1 fonts and CFF fonts without charset names)
+fn use_encoding(font: &(dyn Font + Sync + Send)) -> Option<Vec<(GlyphId, SmallString)>> {
+    let map = font.encoding()?.forward_map()?;
+    let mut v = vec![];
+    for code in 0 ..= 255u8 {
+        if let (Some(c), Some(gid)) = (map.get(code), font.gid_for_codepoint(code as u32)) {
+            v.push((gid, c.into()));
         }
-        let contours = outline.contours().iter().map(points_set).collect();
-        self.entries.push(Entry { data: value, contour_sets: contours });
     }
-    pub fn get(&self, outline: &pathfinder_content::outline::Outline, mut report: Option<&mut String>) -> Option<&I> {
-        use std::fmt::Write;
-
-        let mut candiates: HashMap<usize, usize> = HashMap::new();
-        let mut points_seen = HashSet::new();
-
-        for c in outline.contours().iter() {
-            for &p in c.points().iter() {
-                let key = (p.x() as u16, p.y() as u16);
+    if !v.is_empty() {
+        Some(v)
+    } else {
+        None
+    }
+}
 
-                if points_seen.insert(key) {
-                    if let Some(list) = self.points.get(&key) {
-                        for &idx in list {
-                            *candiates.entry(idx).or_default() += 1;
-                        }
-                    }
-                }
+/// Decode a glyph name following the Adobe Glyph List conventions.
+///
+/// Suffixes after a period are ignored (`a.sc`), ligatures are joined by underscores (`f_i`),
+/// and `uniXXXX` (any number of 4 digit groups) or `uXXXX` to `uXXXXXX` name code points directly.
+pub fn glyph_name_to_unicode(name: &str) -> Option<SmallString> {
+    if let Some(s) = glyphname_to_unicode(name) {
+        return Some(s.into());
+    }
+    let base = name.split('.').next()?;
+    if base.is_empty() {
+        return None;
+    }
+    let mut out = String::new();
+    for part in base.split('_') {
+        if let Some(s) = glyphname_to_unicode(part) {
+            out.push_str(s);
+        } else if let Some(hex) = part.strip_prefix("uni").filter(|h| h.len() >= 4 && h.len() % 4 == 0) {
+            for i in (0 .. hex.len()).step_by(4) {
+                let n = u32::from_str_radix(hex.get(i .. i + 4)?, 16).ok()?;
+                out.push(char::from_u32(n)?);
             }
+        } else if let Some(hex) = part.strip_prefix('u').filter(|h| (4 ..= 6).contains(&h.len())) {
+            let n = u32::from_str_radix(hex, 16).ok()?;
+            out.push(char::from_u32(n)?);
+        } else {
+            return None;
         }
-        let mut candiates: Vec<_> = candiates.into_iter().collect();
-        candiates.sort_by_key(|t| t.1);
-        
-        for &(idx, n) in candiates.iter().rev() {
-            let e = &self.entries[idx];
-            if let Some(report) = report.as_deref_mut() {
-                writeln!(report, "<div>candiate <span>{}</span>", e.data);
-            };
-            if e.contour_sets.len() != outline.contours().len() {
-                if let Some(report) = report.as_deref_mut() {
-                    writeln!(report, " incorrect number of contours {} != {}</div>", e.contour_sets.len(), outline.contours().len());
-                }
-                continue;
-            }
-
-            let mut used = vec![false; outline.contours().len()];
-            for t_c in outline.contours().iter() {
-                let t_s = points_set(t_c);
-                for (r_c_i, r_s) in e.contour_sets.iter().enumerate() {
-                    if used[r_c_i] {
-                        continue;
-                    }
-
-                    if t_s == *r_s {
-                        used[r_c_i] = true;
-                    }
-                }
-            }
+    }
+    Some(out.as_str().into())
+}
 
-            if used.iter().all(|&b| b) {
-                if let Some(report) = report.as_deref_mut() {
-                    writeln!(report, "<p>Unicode: <span>{}</span>, {used:?}</p>", e.data).unwrap();
-                    writeln!(report, "</div>");
-                }
-                return Some(&e.data);
-            }
-        }
-        /*
-        let mut best_entry = None;
-        for e in self.entries.iter() {
-            let n = outline.0.len();
-            let mut score = vec![0.0; n * n];
+impl<I: Display + PartialEq> ShapeDb<I> {
+    /// add `outline` (in font units, `font_matrix` maps them to em units)
+    pub fn add_outline(&mut self, outline: &Outline, font_matrix: Transform2F, value: I) {
+        let (outline, transform) = prepare(&self.params, outline, font_matrix);
+        let contours = outline.contours().iter().map(points_set).collect();
+        let points = outline.contours().iter().map(|c| canonical_points(c).iter().map(|p| (p.x(), p.y())).collect()).collect();
+        self.push_entry(Entry { data: value, contour_sets: contours, outline: points, transform: transform_to_array(transform) });
+    }
+    pub fn get(&self, outline: &Outline, font_matrix: Transform2F, report: Option<&mut String>) -> Option<&I> {
+        find(self, outline, font_matrix, report).map(|idx| &self.entries[idx].data)
+    }
 
-            if e.outline.0.len() != outline.0.len() {
-                continue;
-            }
-            for (t_i, t_c) in outline.0.iter().enumerate() {
-                for (r_i, r_c) in e.outline.0.iter().enumerate() {
-                    score[t_i * n + r_i] = frechet_distance(t_c, r_c);
-                }
-            }
+    /// Returns up to `k` entries, best first.
+    ///
+    /// Candidates that share points with `outline` are scored by the fraction of shared points
+    /// and matched contours. If none of them matches all contours, the fuzzy matcher adds its results.
+    pub fn get_ranked(&self, outline: &Outline, font_matrix: Transform2F, k: usize) -> Vec<RankedMatch<'_, I>> {
+        rank(self, outline, font_matrix, k).into_iter().map(|r| RankedMatch {
+            data: &self.entries[r.idx].data,
+            kind: r.kind,
+            score: r.score,
+            votes: r.votes,
+            matched_contours: r.matched_contours,
+            margin: r.margin,
+        }).collect()
+    }
+}
 
-            let sum: f32 = score.windows(n).map(|w| w.iter().cloned().reduce(|a, b| min(a, b)).unwrap()).sum();
+impl<I: Display> ShapeData for ShapeDb<I> {
+    type Label<'a> = &'a I where I: 'a;
 
-            if let Some(report) = report.as_deref_mut() {
-                if debug.as_ref().map(|d| *d == e.data).unwrap_or(false) {
-                    for w in score.windows(n) {
-                        for s in w {
-                            print!("  {s:5.0}");
-                        }
-                        println!();
-                    }
-                }
+    fn params(&self) -> &BuildParams {
+        &self.params
+    }
+    fn fuzzy_threshold(&self) -> Option<f32> {
+        self.fuzzy_threshold
+    }
+    fn cyclic_fuzzy(&self) -> bool {
+        self.cyclic_fuzzy
+    }
+    fn num_entries(&self) -> usize {
+        self.entries.len()
+    }
+    fn label(&self, idx: usize) -> &I {
+        &self.entries[idx].data
+    }
+    fn query_points(&self, key: PointKey, f: &mut dyn FnMut(usize)) {
+        self.points.query(key, f)
+    }
+    fn num_contours(&self, idx: usize) -> usize {
+        self.entries[idx].contour_sets.len()
+    }
+    fn contour_matches(&self, idx: usize, contour: usize, test: &HashSet<PointKey>) -> bool {
+        sets_match(test, &self.entries[idx].contour_sets[contour], self.params.tolerance)
+    }
+    fn fuzzy_outline(&self, idx: usize) -> Vec<Vec<Vector2F>> {
+        self.entries[idx].outline.iter()
+            .map(|c| c.iter().map(|&(x, y)| Vector2F::new(x, y)).collect())
+            .collect()
+    }
+    fn fuzzy_index(&self) -> &FuzzyIndex {
+        self.fuzzy_index.get_or_init(|| FuzzyIndex::build(self))
+    }
+}
 
-                if sum < 100. * n as f32 {
-                    writeln!(report, "<div><span>{}</span>: {sum}</div>", e.data);
-                }
-            }
-            
-            match best_entry {
-                Some((_, s2)) if sum >= s2 => {}
-                _ => {
-                    best_entry = Some((e, sum));
-                    //println!("{}", score.iter().format(", "));
-                }
-            }
-        }
-        if let Some((e, sum)) = best_entry {
-            if let Some(report) = report.as_deref_mut() {
-                writeln!(report, "<p>Best match: <span>{}</span>, {sum}</p>", e.data).unwrap();
-            }
-            return Some(&e.data);
-        }
-        */
+pub fn check_font<I: Display + PartialEq + Clone>(db: &ShapeDb<I>, ps_name: &str, font: &(dyn Font + Sync + Send), report: Option<&mut String>) -> Result<HashMap<GlyphId, I>> {
+    check_glyphs(db, ps_name, font, &|_| true, report)
+}
 
-        None
+/// Like `check_font`, but only glyphs for which `filter` returns true are matched.
+///
+/// The glyphs are matched one by one, see `par_check_glyphs` to use the `parallel` feature.
+pub fn check_glyphs<I: Display + PartialEq + Clone>(db: &ShapeDb<I>, _ps_name: &str, font: &(dyn Font + Sync + Send), filter: &dyn Fn(GlyphId) -> bool, report: Option<&mut String>) -> Result<HashMap<GlyphId, I>> {
+    match report {
+        None => Ok(match_glyphs_seq(&|outline, font_matrix| db.get(outline, font_matrix, None).cloned(), font, filter)),
+        Some(report) => Ok(report_glyphs(&|outline, font_matrix, report| db.get(outline, font_matrix, report).cloned(), font, filter, report)),
     }
 }
 
-fn points_set(contour: &Contour) -> HashSet<(u16, u16)> {
-    contour.points().iter().map(|&p| (p.x() as u16, p.y() as u16)).collect()
+/// Like `check_glyphs` without a report.
+///
+/// With the `parallel` feature the glyphs are matched on the rayon thread pool.
+pub fn par_check_glyphs<I: Display + PartialEq + Clone + Send + Sync>(db: &ShapeDb<I>, _ps_name: &str, font: &(dyn Font + Sync + Send), filter: &dyn Fn(GlyphId) -> bool) -> Result<HashMap<GlyphId, I>> {
+    Ok(match_glyphs(&|outline, font_matrix| db.get(outline, font_matrix, None).cloned(), font, filter))
 }
 
-pub fn check_font(db: &ShapeDb<SmallString>, ps_name: &str, font: &(dyn Font + Sync + Send), mut report: Option<&mut String>) -> Option<HashMap<GlyphId, SmallString>> {
+/// like `Lookup`, writing the steps of the lookup to the report if given
+type ReportLookup<'a, I> = dyn Fn(&Outline, Transform2F, Option<&mut String>) -> Option<I> + 'a;
+
+/// match the glyphs one by one and write an HTML report of every lookup
+fn report_glyphs<I>(lookup: &ReportLookup<I>, font: &(dyn Font + Sync + Send), filter: &dyn Fn(GlyphId) -> bool, report: &mut String) -> HashMap<GlyphId, I> {
     use std::fmt::Write;
 
+    let mut report = Some(report);
     if let Some(report) = report.as_deref_mut() {
         report.push_str(r#"<!DOCTYPE html>
 <html>
@@ -282,29 +503,185 @@ p > span {
     let mut map = HashMap::new();
 
     for i in 0 .. font.num_glyphs() {
+        if !filter(GlyphId(i)) {
+            continue;
+        }
         if let Some(g) = font.glyph(GlyphId(i)) {
-            if g.path.len() > 0 {
-                if g.path.len() > 0 {
-                    if let Some(report) = report.as_deref_mut() {
-                        writeln!(report, r#"<div class="test">Glyph {i}"#).unwrap();
-                        write_glyph(report, &g.path);
-                    }
-                    if let Some(s) = db.get(&g.path, report.as_deref_mut()) {
-                        map.insert(GlyphId(i), s.clone());
-                    }
-                    if let Some(report) = report.as_deref_mut() {
-                        writeln!(report, "</div>").unwrap();
-                    }
+            if !g.path.is_empty() {
+                if let Some(report) = report.as_deref_mut() {
+                    writeln!(report, r#"<div class="test">Glyph {i}"#).unwrap();
+                    write_glyph(report, &g.path);
+                }
+                if let Some(s) = lookup(&g.path, font.font_matrix(), report.as_deref_mut()) {
+                    map.insert(GlyphId(i), s);
+                }
+                if let Some(report) = report.as_deref_mut() {
+                    writeln!(report, "</div>").unwrap();
                 }
             }
         }
     }
 
-    if let Some(report) = report.as_deref_mut() {
+    if let Some(report) = report {
         report.push_str("</body></html>");
     }
 
-    Some(map)
+    map
+}
+
+/// a database loaded by `FontDb`, decoded or queried in place (see `mapped`)
+pub enum ReferenceDb {
+    Decoded(ShapeDb<SmallString>),
+    Mapped(MappedDb),
+}
+impl ReferenceDb {
+    /// see `ShapeDb::set_fuzzy_threshold` and `ShapeDb::set_cyclic_fuzzy`
+    fn set_fuzzy(&mut self, fuzzy: FuzzySettings) {
+        match self {
+            ReferenceDb::Decoded(db) => {
+                db.set_fuzzy_threshold(fuzzy.threshold);
+                db.set_cyclic_fuzzy(fuzzy.cyclic);
+            }
+            ReferenceDb::Mapped(db) => {
+                db.set_fuzzy_threshold(fuzzy.threshold);
+                db.set_cyclic_fuzzy(fuzzy.cyclic);
+            }
+        }
+    }
+    pub fn len(&self) -> usize {
+        match self {
+            ReferenceDb::Decoded(db) => db.len(),
+            ReferenceDb::Mapped(db) => db.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn params(&self) -> &BuildParams {
+        match self {
+            ReferenceDb::Decoded(db) => db.params(),
+            ReferenceDb::Mapped(db) => &db.header().params,
+        }
+    }
+    pub fn get(&self, outline: &Outline, font_matrix: Transform2F, report: Option<&mut String>) -> Option<SmallString> {
+        match self {
+            ReferenceDb::Decoded(db) => db.get(outline, font_matrix, report).cloned(),
+            ReferenceDb::Mapped(db) => db.get(outline, font_matrix, report).map(SmallString::from),
+        }
+    }
+    /// see `ShapeDb::cache_key`
+    pub fn cache_key(&self) -> Option<u64> {
+        match self {
+            ReferenceDb::Decoded(db) => db.cache_key(),
+            ReferenceDb::Mapped(db) => Some(db.cache_key()),
+        }
+    }
+    /// see `ShapeDb::outline_hash`
+    pub fn outline_hash(&self, outline: &Outline, font_matrix: Transform2F) -> u64 {
+        match self {
+            ReferenceDb::Decoded(db) => db.outline_hash(outline, font_matrix),
+            ReferenceDb::Mapped(db) => db.outline_hash(outline, font_matrix),
+        }
+    }
+}
+
+impl ShapeData for ReferenceDb {
+    type Label<'a> = &'a str;
+
+    fn params(&self) -> &BuildParams {
+        ReferenceDb::params(self)
+    }
+    fn fuzzy_threshold(&self) -> Option<f32> {
+        match self {
+            ReferenceDb::Decoded(db) => db.fuzzy_threshold(),
+            ReferenceDb::Mapped(db) => ShapeData::fuzzy_threshold(db),
+        }
+    }
+    fn cyclic_fuzzy(&self) -> bool {
+        match self {
+            ReferenceDb::Decoded(db) => db.cyclic_fuzzy(),
+            ReferenceDb::Mapped(db) => db.cyclic_fuzzy(),
+        }
+    }
+    fn num_entries(&self) -> usize {
+        self.len()
+    }
+    fn label(&self, idx: usize) -> &str {
+        match self {
+            ReferenceDb::Decoded(db) => &db.entries[idx].data,
+            ReferenceDb::Mapped(db) => db.label(idx),
+        }
+    }
+    fn query_points(&self, key: PointKey, f: &mut dyn FnMut(usize)) {
+        match self {
+            ReferenceDb::Decoded(db) => db.query_points(key, f),
+            ReferenceDb::Mapped(db) => db.query_points(key, f),
+        }
+    }
+    fn num_contours(&self, idx: usize) -> usize {
+        match self {
+            ReferenceDb::Decoded(db) => db.num_contours(idx),
+            ReferenceDb::Mapped(db) => db.num_contours(idx),
+        }
+    }
+    fn contour_matches(&self, idx: usize, contour: usize, test: &HashSet<PointKey>) -> bool {
+        match self {
+            ReferenceDb::Decoded(db) => db.contour_matches(idx, contour, test),
+            ReferenceDb::Mapped(db) => db.contour_matches(idx, contour, test),
+        }
+    }
+    fn fuzzy_outline(&self, idx: usize) -> Vec<Vec<Vector2F>> {
+        match self {
+            ReferenceDb::Decoded(db) => db.fuzzy_outline(idx),
+            ReferenceDb::Mapped(db) => db.fuzzy_outline(idx),
+        }
+    }
+    fn fuzzy_index(&self) -> &FuzzyIndex {
+        match self {
+            ReferenceDb::Decoded(db) => db.fuzzy_index(),
+            ReferenceDb::Mapped(db) => db.fuzzy_index(),
+        }
+    }
+}
+
+/// looks up the label of an outline with its font matrix
+type Lookup<'a, I> = dyn Fn(&Outline, Transform2F) -> Option<I> + Sync + 'a;
+
+fn match_glyph<I>(lookup: &dyn Fn(&Outline, Transform2F) -> Option<I>, font: &(dyn Font + Sync + Send), i: u32) -> Option<(GlyphId, I)> {
+    let g = font.glyph(GlyphId(i))?;
+    if g.path.is_empty() {
+        return None;
+    }
+    Some((GlyphId(i), lookup(&g.path, font.font_matrix())?))
+}
+
+fn match_glyphs_seq<I>(lookup: &dyn Fn(&Outline, Transform2F) -> Option<I>, font: &(dyn Font + Sync + Send), filter: &dyn Fn(GlyphId) -> bool) -> HashMap<GlyphId, I> {
+    (0 .. font.num_glyphs())
+        .filter(|&i| filter(GlyphId(i)))
+        .filter_map(|i| match_glyph(lookup, font, i))
+        .collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn match_glyphs<I>(lookup: &Lookup<I>, font: &(dyn Font + Sync + Send), filter: &dyn Fn(GlyphId) -> bool) -> HashMap<GlyphId, I> {
+    match_glyphs_seq(lookup, font, filter)
+}
+
+/// number of glyphs matched by one task at least
+#[cfg(feature = "parallel")]
+const GLYPH_CHUNK: usize = 64;
+
+#[cfg(feature = "parallel")]
+fn match_glyphs<I: Send>(lookup: &Lookup<I>, font: &(dyn Font + Sync + Send), filter: &dyn Fn(GlyphId) -> bool) -> HashMap<GlyphId, I> {
+    use rayon::prelude::*;
+
+    // the filter need not be `Sync`, it is cheap compared to matching
+    let selected: Vec<u32> = (0 .. font.num_glyphs()).filter(|&i| filter(GlyphId(i))).collect();
+    // the glyphs are matched in any order, the result is the same as that of `match_glyphs_seq`
+    selected.into_par_iter()
+        .with_min_len(GLYPH_CHUNK)
+        .filter_map(|i| match_glyph(lookup, font, i))
+        .collect()
 }
 
 fn write_glyph(w: &mut String, path: &pathfinder_content::outline::Outline) {
@@ -314,44 +691,608 @@ fn write_glyph(w: &mut String, path: &pathfinder_content::outline::Outline) {
     writeln!(w, r#"<svg viewBox="{} {} {} {}" transform="scale(1, -1)" style="display: inline-block;" width="{}px"><path d="{:?}" /></svg>"#, b.min_x(), b.min_y(), b.width(), b.height(), b.width() * 0.05, path, ).unwrap();
 }
 
+/// bytes at the start of a database file that hold its header
+const SUMMARY_LEN: u64 = 4096;
+
+/// source hash and number of entries of the database file at `path`, read from its header
+fn read_summary(path: &Path) -> Result<(u64, usize)> {
+    use std::io::Read;
+
+    let mut data = vec![];
+    std::fs::File::open(path)?.take(SUMMARY_LEN).read_to_end(&mut data)?;
+    if is_mapped(&data) {
+        let (header, _) = parse_mapped_header(&data)?;
+        Ok((header.source_hash, header.num_entries as usize))
+    } else {
+        let (header, len) = format::read_summary(&data)?;
+        Ok((header.source_hash, len))
+    }
+}
+
+/// whether a file in a database directory named `name` can be a database,
+/// files ending in `TMP_SUFFIX` are left over from interrupted writes
+fn is_db_file_name(name: &str) -> bool {
+    name != CATALOG_FILE && name != ALIAS_FILE && !name.ends_with(TMP_SUFFIX)
+}
+
+enum Storage {
+    /// a directory with one file per font
+    Dir(PathBuf),
+    /// a single pack file
+    Pack(Pack),
+}
+
+/// result of `FontDb::check_font`
+#[derive(Debug, Clone, Default)]
+pub struct FontMatch {
+    pub glyphs: HashMap<GlyphId, SmallString>,
+    /// reference fonts that labeled glyphs and how many, most first
+    pub sources: Vec<(String, usize)>,
+    /// how the requested name was mapped to a reference font, `None` if all fonts were searched
+    pub resolved: Option<Resolved>,
+}
+
+/// labeled glyphs of each source that `FontDb::check_font_layered` compares with their shape
+const LAYER_SAMPLE: usize = 64;
+
+/// fuzzy matching settings of the databases loaded by `FontDb`
+#[derive(Copy, Clone)]
+struct FuzzySettings {
+    threshold: Option<f32>,
+    cyclic: bool,
+}
+impl Default for FuzzySettings {
+    fn default() -> Self {
+        FuzzySettings { threshold: default_fuzzy_threshold(), cyclic: false }
+    }
+}
+
 pub struct FontDb {
-    path: PathBuf,
-    cache: RwLock<HashMap<String, Option<Arc<ShapeDb<SmallString>>>>>,
+    storage: Storage,
+    cache: Cache<Option<Arc<ReferenceDb>>>,
+    /// requested font name → reference font
+    resolved: Cache<Option<Resolved>>,
+    aliases: Slot<AliasConfig>,
+    global: Slot<GlobalIndex>,
+    memo: Slot<ResultCache>,
+    fuzzy: Slot<FuzzySettings>,
+    /// canonical name → PostScript name of the fonts in the catalog, see `find_by_name`
+    names: Slot<HashMap<String, String>>,
+    /// PostScript name → hash of the font file in the catalog, see `catalog_source_hash`
+    source_hashes: Slot<HashMap<String, u64>>,
 }
 impl FontDb {
+    /// use the database directory at `path`
     pub fn new(path: impl Into<PathBuf>) -> Self {
-        FontDb { path: path.into(), cache: Default::default() }
+        FontDb::with_storage(Storage::Dir(path.into()))
+    }
+    /// open a database directory or a pack file
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let storage = if path.is_file() {
+            Storage::Pack(Pack::open(path)?)
+        } else {
+            Storage::Dir(path)
+        };
+        Ok(FontDb::with_storage(storage))
+    }
+    fn with_storage(storage: Storage) -> Self {
+        FontDb {
+            storage,
+            cache: Default::default(),
+            resolved: Default::default(),
+            aliases: Default::default(),
+            global: Default::default(),
+            memo: Default::default(),
+            fuzzy: Default::default(),
+            names: Default::default(),
+            source_hashes: Default::default(),
+        }
+    }
+    fn dir(&self) -> Result<&Path> {
+        match self.storage {
+            Storage::Dir(ref path) => Ok(path),
+            Storage::Pack(_) => Err(Error::ReadOnly),
+        }
+    }
+    pub fn scan(&self) -> Result<()> {
+        let result = init(self.dir()?);
+        self.source_hashes.set(None);
+        self.names.set(None);
+        result
+    }
+    fn has_font(&self, ps_name: &str) -> bool {
+        if ps_name.contains(['/', '\\']) {
+            return false;
+        }
+        match self.storage {
+            Storage::Dir(ref path) => is_db_file_name(ps_name) && path.join(ps_name).is_file(),
+            Storage::Pack(ref pack) => pack.catalog().any(|e| e.ps_name == ps_name),
+        }
+    }
+    /// Limit the loaded databases and remembered font names, evicting the least recently used.
+    pub fn set_cache_policy(&self, policy: CachePolicy) {
+        self.cache.set_policy(policy.clone());
+        self.resolved.set_policy(CachePolicy { max_bytes: None, ..policy });
+    }
+    pub fn cache_policy(&self) -> CachePolicy {
+        self.cache.policy()
+    }
+    /// number of cached databases, including fonts that were not found, and the size of their files
+    pub fn cache_usage(&self) -> (usize, u64) {
+        self.cache.usage()
+    }
+    /// Forget the database of `ps_name`, so it is read again on next use.
+    ///
+    /// Forgets the names that resolve to `ps_name`, names that were not found and fallbacks,
+    /// as a new font may be a better match for them. The global index and the name table are
+    /// reset if they contain `ps_name` or the font is new.
+    pub fn invalidate(&self, ps_name: &str) {
+        self.cache.remove(ps_name);
+        self.resolved.retain(|_, resolved| match resolved {
+            Some(r) => r.ps_name != ps_name && !matches!(r.resolution, Resolution::Fallback { .. }),
+            None => false,
+        });
+        let exists = self.has_font(ps_name);
+        if self.global.get().is_some_and(|index| exists || index.contains(ps_name)) {
+            self.global.set(None);
+        }
+        if self.names.get().is_some_and(|names| exists || names.values().any(|n| n == ps_name)) {
+            self.names.set(None);
+        }
+        self.source_hashes.set(None);
+    }
+    /// Forget all loaded databases, name resolutions, aliases and the global index.
+    ///
+    /// Use this after database files were added, replaced or removed by another process.
+    pub fn reload(&self) {
+        self.cache.clear();
+        self.resolved.clear();
+        self.global.set(None);
+        self.aliases.set(None);
+        self.names.set(None);
+        self.source_hashes.set(None);
+    }
+    fn fuzzy(&self) -> FuzzySettings {
+        self.fuzzy.get().map(|f| *f).unwrap_or_default()
+    }
+    fn set_fuzzy(&self, fuzzy: FuzzySettings) {
+        self.fuzzy.set(Some(Arc::new(fuzzy)));
+        // loaded databases keep the settings they were loaded with
+        self.cache.clear();
+        self.global.set(None);
+    }
+    /// Set the fuzzy matching threshold of all databases, see `ShapeDb::set_fuzzy_threshold`.
+    ///
+    /// Loaded databases and the global index are dropped and loaded again with the new setting.
+    pub fn set_fuzzy_threshold(&self, threshold: Option<f32>) {
+        self.set_fuzzy(FuzzySettings { threshold, ..self.fuzzy() });
+    }
+    pub fn fuzzy_threshold(&self) -> Option<f32> {
+        self.fuzzy().threshold
+    }
+    /// see `ShapeDb::set_cyclic_fuzzy` and `set_fuzzy_threshold`
+    pub fn set_cyclic_fuzzy(&self, cyclic: bool) {
+        self.set_fuzzy(FuzzySettings { cyclic, ..self.fuzzy() });
+    }
+    /// Remember the results of lookups in `cache`, `None` disables caching.
+    pub fn set_result_cache(&self, cache: Option<Arc<ResultCache>>) {
+        self.memo.set(cache);
+    }
+    pub fn result_cache(&self) -> Option<Arc<ResultCache>> {
+        self.memo.get()
+    }
+    /// Alias configuration, read from `aliases.json` on first use.
+    ///
+    /// Packs have none unless set with `set_aliases`.
+    pub fn aliases(&self) -> Result<Arc<AliasConfig>> {
+        if let Some(aliases) = self.aliases.get() {
+            return Ok(aliases);
+        }
+        let aliases = match self.storage {
+            Storage::Dir(ref path) => AliasConfig::load_dir(path)?,
+            Storage::Pack(_) => AliasConfig::default(),
+        };
+        let aliases = Arc::new(aliases);
+        self.aliases.set(Some(aliases.clone()));
+        Ok(aliases)
+    }
+    pub fn set_aliases(&self, aliases: AliasConfig) {
+        self.aliases.set(Some(Arc::new(aliases)));
+        self.resolved.clear();
+    }
+    /// re-read `aliases.json` on next use
+    pub fn reload_aliases(&self) {
+        self.aliases.set(None);
+        self.resolved.clear();
+    }
+    /// Find the database for `name` by name only.
+    ///
+    /// Subset tags are ignored and the styles and vendor suffixes are normalized
+    /// (see `names::lookup_names`), falling back to known aliases of the family.
+    fn find_by_name(&self, name: &str) -> Result<Option<Resolved>> {
+        let names = lookup_names(name);
+        let resolved = |ps_name: &str| Resolved {
+            resolution: if ps_name == name { Resolution::Exact } else { Resolution::Normalized },
+            ps_name: ps_name.into(),
+        };
+        if let Some(n) = names.iter().find(|n| self.has_font(n)) {
+            return Ok(Some(resolved(n)));
+        }
+        let table = self.name_table()?;
+        for n in names.iter() {
+            if let Some(ps_name) = table.get(&normalize_ps_name(n)) {
+                return Ok(Some(resolved(ps_name)));
+            }
+        }
+        Ok(None)
     }
-    pub fn scan(&self) {
-        init(&self.path)
+    /// canonical names of the fonts in the catalog, built on first use
+    fn name_table(&self) -> Result<Arc<HashMap<String, String>>> {
+        if let Some(table) = self.names.get() {
+            return Ok(table);
+        }
+        let mut table = HashMap::new();
+        for e in self.catalog()? {
+            // the first font in the catalog wins
+            table.entry(normalize_ps_name(&e.ps_name)).or_insert(e.ps_name);
+        }
+        let table = Arc::new(table);
+        self.names.set(Some(table.clone()));
+        Ok(table)
     }
-    fn get_db(&self, ps_name: &str) -> Option<Arc<ShapeDb<SmallString>>> {
-        if let Some(cached) = self.cache.read().unwrap().get(ps_name) {
-            return cached.clone();
+    /// Find the reference font to use for the font `name`.
+    ///
+    /// A database with exactly the requested name is used first, then an explicit alias,
+    /// then the normalized name (see `find_by_name`) and finally the fallback chain of the name.
+    pub fn resolve(&self, name: &str) -> Result<Option<Resolved>> {
+        if let Some(resolved) = self.resolved.get(name) {
+            return Ok(resolved);
         }
 
-        let file_path = self.path.join(ps_name);
-        let db = if file_path.is_file() {
-            Some(Arc::new(postcard::from_bytes(&std::fs::read(&file_path).unwrap()).unwrap()))
-        } else {
-            None
+        let aliases = self.aliases()?;
+        let by_name = self.find_by_name(name)?;
+        let mut resolved = by_name.clone().filter(|r| r.resolution == Resolution::Exact);
+        if resolved.is_none() {
+            if let Some(target) = aliases.alias(name) {
+                match self.find_by_name(target)? {
+                    Some(r) => resolved = Some(Resolved { ps_name: r.ps_name, resolution: Resolution::Alias }),
+                    None => log::warn!("alias {} of {} is not in the database", target, name),
+                }
+            }
+        }
+        if resolved.is_none() {
+            resolved = by_name;
+        }
+        if resolved.is_none() {
+            if let Some((key, chain)) = aliases.fallbacks(name) {
+                for (position, fallback) in chain.iter().enumerate() {
+                    if let Some(r) = self.find_by_name(fallback)? {
+                        resolved = Some(Resolved { ps_name: r.ps_name, resolution: Resolution::Fallback { key, position } });
+                        break;
+                    }
+                }
+            }
+        }
+        self.resolved.insert(name.into(), resolved.clone(), 0, resolved.is_none());
+        Ok(resolved)
+    }
+    /// PostScript name of the reference font to use for the font `name`
+    pub fn resolve_name(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.resolve(name)?.map(|r| r.ps_name))
+    }
+    /// read the database file of `ps_name`
+    fn read_db_file(&self, ps_name: &str) -> Result<Option<Vec<u8>>> {
+        match self.storage {
+            Storage::Dir(ref path) => {
+                let file_path = path.join(ps_name);
+                if file_path.is_file() && is_db_file_name(ps_name) {
+                    Ok(Some(std::fs::read(&file_path)?))
+                } else {
+                    Ok(None)
+                }
+            }
+            Storage::Pack(ref pack) => pack.read(ps_name),
+        }
+    }
+    /// Hash of the font file `ps_name` was built from according to the catalog, if it is listed.
+    ///
+    /// The catalog is read on first use and kept until `invalidate`, `reload` or `scan`.
+    fn catalog_source_hash(&self, ps_name: &str) -> Result<Option<u64>> {
+        if let Some(hashes) = self.source_hashes.get() {
+            return Ok(hashes.get(ps_name).copied());
+        }
+        let hashes: HashMap<String, u64> = match self.storage {
+            Storage::Dir(ref path) => Catalog::load_dir(path)?.fonts.into_iter().map(|e| (e.ps_name, e.source_hash)).collect(),
+            Storage::Pack(ref pack) => pack.catalog().map(|e| (e.ps_name.clone(), e.source_hash)).collect(),
+        };
+        let hash = hashes.get(ps_name).copied();
+        self.source_hashes.set(Some(Arc::new(hashes)));
+        Ok(hash)
+    }
+    /// List the fonts in the database.
+    ///
+    /// Database files in a directory that are missing from its catalog are listed as well.
+    pub fn catalog(&self) -> Result<Vec<CatalogEntry>> {
+        let path = match self.storage {
+            Storage::Pack(ref pack) => return Ok(pack.catalog().cloned().collect()),
+            Storage::Dir(ref path) => path,
+        };
+        let mut catalog = Catalog::load_dir(path)?;
+        for e in std::fs::read_dir(path)?.filter_map(|r| r.ok()) {
+            let Ok(name) = e.file_name().into_string() else { continue };
+            if !is_db_file_name(&name) || catalog.get(&name).is_some() || !e.path().is_file() {
+                continue;
+            }
+            let (source_hash, glyph_count) = match read_summary(&e.path()) {
+                Ok(summary) => summary,
+                Err(err) => {
+                    log::warn!("{name} in the database directory is not a database: {err}");
+                    continue;
+                }
+            };
+            catalog.insert(CatalogEntry {
+                ps_name: name,
+                family: None,
+                style: None,
+                version: None,
+                glyph_count,
+                source_path: String::new(),
+                source_hash,
+            });
+        }
+        Ok(catalog.fonts)
+    }
+    /// write all fonts of the database into the pack file `out`
+    pub fn pack(&self, out: &Path) -> Result<()> {
+        let mut fonts = vec![];
+        for entry in self.catalog()? {
+            if let Some(data) = self.read_db_file(&entry.ps_name)? {
+                fonts.push((entry, data));
+            }
+        }
+        write_pack(out, fonts)
+    }
+    /// Load the database file of `ps_name`, without resolving the name.
+    /// Files that fail to load are not cached, so they are retried on the next call.
+    ///
+    /// Files in the mapped layout are opened in place, others are decoded.
+    fn load_db(&self, ps_name: &str) -> Result<Option<Arc<ReferenceDb>>> {
+        if let Some(cached) = self.cache.get(ps_name) {
+            return Ok(cached);
+        }
+
+        let (db, size) = match self.read_reference_db(ps_name)? {
+            Some((db, size)) => (Some(Arc::new(db)), size),
+            None => (None, 0),
+        };
+        self.cache.insert(ps_name.into(), db.clone(), size, db.is_none());
+        Ok(db)
+    }
+    /// Read the database of `ps_name` without caching it.
+    ///
+    /// Also returns the size of the file, which counts against `CachePolicy::max_bytes`.
+    fn read_reference_db(&self, ps_name: &str) -> Result<Option<(ReferenceDb, u64)>> {
+        let mapped_path = match self.storage {
+            Storage::Dir(ref path) if self.has_font(ps_name) => Some(path.join(ps_name)),
+            _ => None,
+        };
+        let (db, size) = match mapped_path {
+            Some(ref path) if is_mapped_file(path)? => {
+                let db = MappedDb::open(path)?;
+                (Some((db.header().source_hash, ReferenceDb::Mapped(db))), std::fs::metadata(path)?.len())
+            }
+            _ => match self.read_db_file(ps_name)? {
+                Some(data) => {
+                    let size = data.len() as u64;
+                    let db = if is_mapped(&data) {
+                        let db = MappedDb::from_vec(data)?;
+                        (db.header().source_hash, ReferenceDb::Mapped(db))
+                    } else {
+                        let (header, db) = ShapeDb::from_bytes_with_header(&data)?;
+                        (header.source_hash, ReferenceDb::Decoded(db))
+                    };
+                    (Some(db), size)
+                }
+                None => (None, 0),
+            },
+        };
+        let Some((found, mut db)) = db else {
+            return Ok(None);
+        };
+        if let Some(expected) = self.catalog_source_hash(ps_name)? {
+            if expected != found {
+                return Err(format::FormatError::SourceMismatch { expected, found }.into());
+            }
+        }
+        db.set_fuzzy(self.fuzzy());
+        Ok(Some((db, size)))
+    }
+    /// Returns `Ok(None)` if there is no database for `ps_name` (see `resolve`).
+    fn get_db(&self, ps_name: &str) -> Result<Option<(Resolved, Arc<ReferenceDb>)>> {
+        let Some(resolved) = self.resolve(ps_name)? else {
+            return Ok(None);
         };
-        self.cache.write().unwrap().insert(ps_name.into(), db.clone());
-        db
+        Ok(self.load_db(&resolved.ps_name)?.map(|db| (resolved, db)))
     }
-    pub fn font_report(&self, ps_name: &str, font: &(dyn Font + Sync + Send)) -> String {
+    /// HTML report of the lookup of every glyph of `font`, see `check_font`
+    pub fn font_report(&self, ps_name: &str, font: &(dyn Font + Sync + Send)) -> Result<String> {
         let mut report = String::new();
-        let db = self.get_db(ps_name).unwrap();
-        check_font(&db, ps_name, font, Some(&mut report));
-        report
+        match self.get_db(ps_name)? {
+            Some((resolved, db)) => {
+                if resolved.resolution != Resolution::Exact {
+                    report.push_str(&format!("using {}\n", resolved));
+                }
+                report_glyphs(&|outline, font_matrix, report| db.get(outline, font_matrix, report), font, &|_| true, &mut report);
+            }
+            None => {
+                report.push_str("using all reference fonts\n");
+                let index = self.global_index()?;
+                report_glyphs(&|outline, font_matrix, report| index.get(outline, font_matrix, report).map(|l| l.label), font, &|_| true, &mut report);
+            }
+        }
+        Ok(report)
+    }
+    /// Label the glyphs of `font` using the reference font `ps_name`.
+    ///
+    /// If there is no database for `ps_name`, the glyphs are matched against all reference fonts.
+    /// An unknown font is not an error, errors are only returned if a database cannot be read.
+    pub fn check_font(&self, ps_name: &str, font: &(dyn Font + Sync + Send)) -> Result<Arc<FontMatch>> {
+        self.check_glyphs(ps_name, font, &|_| true).map(Arc::new)
+    }
+    /// like `check_font`, but only glyphs for which `filter` returns true are matched
+    pub fn check_glyphs(&self, ps_name: &str, font: &(dyn Font + Sync + Send), filter: &dyn Fn(GlyphId) -> bool) -> Result<FontMatch> {
+        let Some((resolved, db)) = self.get_db(ps_name)? else {
+            return Ok(self.global_index()?.match_font(self.memo.get().as_deref(), font, filter));
+        };
+        let glyphs = match self.memo.get() {
+            Some(memo) => match_glyphs(&|outline, font_matrix| memo.get_or_lookup(&db, outline, font_matrix), font, filter),
+            None => match_glyphs(&|outline, font_matrix| db.get(outline, font_matrix, None), font, filter),
+        };
+        let sources = vec![(resolved.ps_name.clone(), glyphs.len())];
+        Ok(FontMatch { glyphs, sources, resolved: Some(resolved) })
+    }
+    /// Label the glyphs of `font` by their names and the font's cmap first,
+    /// and match the shapes of the remaining glyphs against `ps_name`.
+    ///
+    /// Up to `LAYER_SAMPLE` glyphs of each source are matched by shape as well. Sources whose labels
+    /// are unreliable by `validate::DEFAULT_THRESHOLD` are dropped and their glyphs are matched by shape.
+    pub fn check_font_layered(&self, ps_name: &str, font: &(dyn Font + Sync + Send)) -> Result<LayeredMatch> {
+        let sources = source_labels(font);
+        let mut samples = HashSet::new();
+        for (_, list) in sources.iter() {
+            let mut gids: Vec<_> = list.iter().map(|&(gid, _)| gid).collect();
+            gids.sort_unstable();
+            gids.dedup();
+            let step = gids.len().div_ceil(LAYER_SAMPLE).max(1);
+            samples.extend(gids.into_iter().step_by(step));
+        }
+        let sampled = match samples.is_empty() {
+            true => HashMap::new(),
+            false => self.check_glyphs(ps_name, font, &|gid| samples.contains(&gid))?.glyphs,
+        };
+
+        let mut kept = vec![];
+        let mut dropped = vec![];
+        for (source, list) in sources {
+            let sample: Vec<_> = list.iter().filter(|(gid, _)| samples.contains(gid)).cloned().collect();
+            let validation = validate_labels(&sample, &sampled, DEFAULT_THRESHOLD);
+            if validation.unreliable {
+                log::info!("labels from {source:?} disagree with the shapes of {ps_name}, not used");
+                dropped.push((source, validation));
+            } else {
+                kept.push((source, list));
+            }
+        }
+
+        let mut labels = merge_labels(&kept);
+        let shapes = self.check_glyphs(ps_name, font, &|gid| !labels.contains_key(&gid))?;
+        for (gid, text) in shapes.glyphs {
+            labels.insert(gid, Label { text, source: LabelSource::Shape });
+        }
+        Ok(LayeredMatch { labels, sources: shapes.sources, resolved: shapes.resolved, dropped })
+    }
+    /// Label the character codes of a simple font, given the glyph of each code
+    /// (see `simple::SimpleEncoding::code_to_gid`).
+    pub fn check_codes(&self, ps_name: &str, font: &(dyn Font + Sync + Send), codes: &[(u8, GlyphId)]) -> Result<BTreeMap<u8, SmallString>> {
+        let gids: HashSet<GlyphId> = codes.iter().map(|&(_, gid)| gid).collect();
+        let result = self.check_glyphs(ps_name, font, &|gid| gids.contains(&gid))?;
+        Ok(label_codes(&result.glyphs, codes))
+    }
+    /// Compare the labels `font` has of its own with the shape matches against `ps_name`,
+    /// separately for each source of labels (see `layers::source_labels`).
+    ///
+    /// Fonts without labels of their own have nothing to compare and are never unreliable.
+    pub fn validate_font(&self, ps_name: &str, font: &(dyn Font + Sync + Send), threshold: f32) -> Result<FontValidation> {
+        let sources = source_labels(font);
+        if sources.is_empty() {
+            return Ok(FontValidation::default());
+        }
+        let shapes = self.check_font(ps_name, font)?;
+        let sources = sources.into_iter()
+            .map(|(source, list)| (source, validate_labels(&list, &shapes.glyphs, threshold)))
+            .collect();
+        Ok(FontValidation { sources })
+    }
+    /// rank the reference fonts by how many glyphs of `font` they contain
+    pub fn identify_font(&self, font: &(dyn Font + Sync + Send)) -> Result<Vec<FontCandidate>> {
+        Ok(self.global_index()?.identify(font))
+    }
+    /// match the glyphs of `font` against all reference fonts
+    pub fn check_font_global(&self, font: &(dyn Font + Sync + Send)) -> Result<FontMatch> {
+        self.global_index()?.check_font(font)
+    }
+    /// Index over all fonts in the database, built on first use.
+    ///
+    /// Databases that are already loaded are shared, others are read without adding them to the cache.
+    /// Fonts built with other parameters than the first one and files that cannot be read are left out.
+    pub fn global_index(&self) -> Result<Arc<GlobalIndex>> {
+        if let Some(index) = self.global.get() {
+            return Ok(index);
+        }
+
+        let mut index: Option<GlobalIndex> = None;
+        for entry in self.catalog()? {
+            let db = match self.cache.get(&entry.ps_name) {
+                Some(Some(db)) => db,
+                _ => match self.read_reference_db(&entry.ps_name) {
+                    Ok(Some((db, _))) => Arc::new(db),
+                    Ok(None) => continue,
+                    Err(e) => {
+                        log::warn!("{} is not added to the global index: {e}", entry.ps_name);
+                        continue;
+                    }
+                },
+            };
+            let index = index.get_or_insert_with(|| GlobalIndex::new(*db.params()));
+            if !index.add(&entry.ps_name, db) {
+                log::warn!("{} was built with different parameters, not added to the global index", entry.ps_name);
+            }
+        }
+        let mut index = index.unwrap_or_else(|| GlobalIndex::new(BuildParams::default()));
+        let fuzzy = self.fuzzy();
+        index.set_fuzzy_threshold(fuzzy.threshold);
+        index.set_cyclic_fuzzy(fuzzy.cyclic);
+        let index = Arc::new(index);
+        self.global.set(Some(index.clone()));
+        Ok(index)
+    }
+    /// Rewrite the database file of `ps_name` in the mapped layout, does nothing if it already is.
+    pub fn map_font(&self, ps_name: &str) -> Result<()> {
+        let dir = self.dir()?;
+        let data = self.read_db_file(ps_name)?.ok_or_else(|| Error::UnknownFont(ps_name.into()))?;
+        if is_mapped(&data) {
+            return Ok(());
+        }
+        let (header, db) = ShapeDb::<SmallString>::from_bytes_with_header(&data)?;
+        let mapped = db.to_mapped_bytes(header.source_hash)?;
+
+        // the old file may still be mapped, so it is replaced instead of overwritten
+        replace_file(&dir.join(ps_name), &mapped)?;
+        self.invalidate(ps_name);
+        Ok(())
     }
-    pub fn check_font(&self, ps_name: &str, font: &(dyn Font + Sync + Send)) -> Option<Arc<HashMap<GlyphId, SmallString>>> {
-        let db = self.get_db(ps_name)?;
-        let out = check_font(&db, ps_name, font, None).map(Arc::new);
-        out
+    /// Build the database of `ps_name` again from the font file recorded in the catalog.
+    ///
+    /// Databases in an older format or built from a different font file have to be rebuilt.
+    pub fn rebuild(&self, ps_name: &str) -> Result<()> {
+        let catalog = Catalog::load_dir(self.dir()?)?;
+        let source_path = catalog.get(ps_name)
+            .map(|e| e.source_path.as_str())
+            .filter(|p| !p.is_empty())
+            .ok_or_else(|| Error::UnknownFont(ps_name.into()))?;
+        self.add_font(Path::new(source_path))
     }
-    pub fn add_font(&self, font_path: &Path) {
-        add_font(&self.path, font_path)
+    /// add a font and record it in the catalog
+    pub fn add_font(&self, font_path: &Path) -> Result<()> {
+        let dir = self.dir()?;
+        let entry = add_font(dir, font_path)?;
+        let ps_name = entry.ps_name.clone();
+        let mut catalog = Catalog::load_dir(dir)?;
+        catalog.insert(entry);
+        catalog.save_dir(dir)?;
+        self.invalidate(&ps_name);
+        Ok(())
     }
 }
 
@@ -0,0 +1,358 @@
+//! Matching of outlines, independent of how the database is stored.
+//!
+//! `ShapeDb` keeps its entries in memory, `mapped::MappedDb` reads them from the file.
+//! Both implement `ShapeData`, and the functions here find the matching entry for either.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Write};
+
+use pathfinder_content::outline::{Contour, Outline};
+use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_geometry::vector::Vector2F;
+
+use crate::assignment::assign;
+use crate::canonical::canonical_points;
+use crate::flatten::resample_outline;
+use crate::frechet::{cyclic_frechet_distance_points, frechet_distance_points};
+use crate::index::{point_key, PointKey};
+use crate::normalize::normalize;
+use crate::{BuildParams, MatchKind};
+
+/// read access to the entries of a shape database
+pub trait ShapeData {
+    type Label<'a>: Display where Self: 'a;
+
+    fn params(&self) -> &BuildParams;
+    /// see `ShapeDb::set_fuzzy_threshold`
+    fn fuzzy_threshold(&self) -> Option<f32>;
+    /// see `ShapeDb::set_cyclic_fuzzy`
+    fn cyclic_fuzzy(&self) -> bool;
+    fn num_entries(&self) -> usize;
+    fn label(&self, idx: usize) -> Self::Label<'_>;
+    /// calls `f` once for every entry that has a point in the neighbourhood of `key`
+    fn query_points(&self, key: PointKey, f: &mut dyn FnMut(usize));
+    fn num_contours(&self, idx: usize) -> usize;
+    /// whether contour `contour` of entry `idx` has the points `test`, within the tolerance
+    fn contour_matches(&self, idx: usize, contour: usize, test: &HashSet<PointKey>) -> bool;
+    /// the canonical points of the contours of entry `idx`, empty if they were not stored
+    fn fuzzy_outline(&self, idx: usize) -> Vec<Vec<Vector2F>>;
+    /// the `FuzzyIndex` of the entries, built on first use
+    fn fuzzy_index(&self) -> &FuzzyIndex;
+}
+
+/// bounding box `[min_x, min_y, max_x, max_y]` of all points of `contours`
+fn bounds(contours: &[Vec<Vector2F>]) -> Option<[f32; 4]> {
+    let mut points = contours.iter().flatten();
+    let first = points.next()?;
+    Some(points.fold([first.x(), first.y(), first.x(), first.y()], |[x0, y0, x1, y1], p| {
+        [x0.min(p.x()), y0.min(p.y()), x1.max(p.x()), y1.max(p.y())]
+    }))
+}
+
+/// Entries that can pass the fuzzy matcher, by number of contours and bounding box.
+///
+/// Every point of a contour is within the frechet distance of a point of the other contour.
+/// An average distance per contour below the threshold allows no single contour pair more than
+/// the threshold times the number of contours, so the bounding boxes of the outlines
+/// cannot differ by more than that on any side.
+#[derive(Default)]
+pub struct FuzzyIndex {
+    /// number of contours → entries with the bounding box of their canonical points
+    by_contours: HashMap<usize, Vec<(usize, [f32; 4])>>,
+}
+impl FuzzyIndex {
+    pub fn build<S: ShapeData + ?Sized>(db: &S) -> Self {
+        let mut by_contours: HashMap<usize, Vec<_>> = HashMap::new();
+        for idx in 0 .. db.num_entries() {
+            let outline = db.fuzzy_outline(idx);
+            // entries without stored points take no part in fuzzy matching
+            if outline.len() != db.num_contours(idx) {
+                continue;
+            }
+            let Some(bounds) = bounds(&outline) else { continue };
+            by_contours.entry(outline.len()).or_default().push((idx, bounds));
+        }
+        FuzzyIndex { by_contours }
+    }
+    /// entries that may be within `threshold` of the canonical `test_contours`, in ascending order
+    pub fn candidates(&self, test_contours: &[Vec<Vector2F>], threshold: f32) -> impl Iterator<Item=usize> + '_ {
+        let n = test_contours.len();
+        let test_bounds = bounds(test_contours);
+        let max_diff = threshold * n as f32;
+        self.by_contours.get(&n).into_iter().flatten()
+            .filter(move |(_, b)| test_bounds.is_some_and(|t| t.iter().zip(b).all(|(t, b)| (t - b).abs() <= max_diff)))
+            .map(|&(idx, _)| idx)
+    }
+}
+
+/// normalize `outline` and resample it if requested by `params`
+pub(crate) fn prepare(params: &BuildParams, outline: &Outline, font_matrix: Transform2F) -> (Outline, Transform2F) {
+    let (outline, transform) = normalize(outline, font_matrix, params.normalization);
+    match params.sample_spacing {
+        Some(spacing) => (resample_outline(&outline, spacing), transform),
+        None => (outline, transform),
+    }
+}
+
+pub(crate) fn points_set(contour: &Contour) -> HashSet<PointKey> {
+    contour.points().iter().map(|&p| point_key(p)).collect()
+}
+
+/// Find the entry matching `outline` (in font units).
+///
+/// Entries that share points with `outline` are tried first, most shared points first.
+/// If none of them matches every contour, the fuzzy matcher is used.
+pub fn find<S: ShapeData + ?Sized>(db: &S, outline: &Outline, font_matrix: Transform2F, report: Option<&mut String>) -> Option<usize> {
+    let (outline, _) = prepare(db.params(), outline, font_matrix);
+    find_prepared(db, &outline, report)
+}
+
+/// like `find`, for an outline that went through `prepare` already
+pub(crate) fn find_prepared<S: ShapeData + ?Sized>(db: &S, outline: &Outline, mut report: Option<&mut String>) -> Option<usize> {
+    let (candiates, _) = candidates(db, outline);
+    let test_sets: Vec<_> = outline.contours().iter().map(points_set).collect();
+
+    for &(idx, _) in candiates.iter() {
+        if let Some(report) = report.as_deref_mut() {
+            writeln!(report, "<div>candiate <span>{}</span>", db.label(idx)).unwrap();
+        };
+        let num_contours = db.num_contours(idx);
+        if num_contours != outline.contours().len() {
+            if let Some(report) = report.as_deref_mut() {
+                writeln!(report, " incorrect number of contours {} != {}</div>", num_contours, outline.contours().len()).unwrap();
+            }
+            continue;
+        }
+
+        let used = match_contours(db, &test_sets, idx);
+
+        if used.iter().all(|&b| b) {
+            if let Some(report) = report.as_deref_mut() {
+                writeln!(report, "<p>Unicode: <span>{}</span>, {used:?}</p>", db.label(idx)).unwrap();
+                writeln!(report, "</div>").unwrap();
+            }
+            return Some(idx);
+        }
+    }
+    if let Some(threshold) = db.fuzzy_threshold() {
+        if let Some((idx, dist)) = fuzzy_match(db, outline, threshold, report.as_deref_mut()) {
+            if let Some(report) = report {
+                writeln!(report, "<p>Best match: <span>{}</span>, {dist}</p>", db.label(idx)).unwrap();
+            }
+            return Some(idx);
+        }
+    }
+
+    None
+}
+
+/// entries sharing points with `outline`, ordered by the number of shared points (most first),
+/// and the number of distinct points in `outline`
+pub(crate) fn candidates<S: ShapeData + ?Sized>(db: &S, outline: &Outline) -> (Vec<(usize, usize)>, usize) {
+    let mut candiates: HashMap<usize, usize> = HashMap::new();
+    let mut points_seen = HashSet::new();
+
+    for c in outline.contours().iter() {
+        for &p in c.points().iter() {
+            let key = point_key(p);
+
+            if points_seen.insert(key) {
+                db.query_points(key, &mut |idx| *candiates.entry(idx).or_default() += 1);
+            }
+        }
+    }
+    let mut candiates: Vec<_> = candiates.into_iter().collect();
+    candiates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    (candiates, points_seen.len())
+}
+
+/// number of point-vote candidates that are scored by `rank`
+const MAX_RANKED_CANDIDATES: usize = 64;
+
+/// a result of `rank`, see `RankedMatch`
+pub(crate) struct Ranked {
+    pub idx: usize,
+    pub kind: MatchKind,
+    pub score: f32,
+    pub votes: usize,
+    pub matched_contours: usize,
+    pub margin: f32,
+}
+
+/// Returns up to `k` entries, best first, see `ShapeDb::get_ranked`.
+pub(crate) fn rank<S: ShapeData + ?Sized>(db: &S, outline: &Outline, font_matrix: Transform2F, k: usize) -> Vec<Ranked> {
+    let (outline, _) = prepare(db.params(), outline, font_matrix);
+    let (candidates, num_points) = candidates(db, &outline);
+    let test_sets: Vec<_> = outline.contours().iter().map(points_set).collect();
+
+    let mut ranked: HashMap<usize, Ranked> = HashMap::new();
+    let mut exact = false;
+    for &(idx, votes) in candidates.iter().take(MAX_RANKED_CANDIDATES.max(k)) {
+        let matched = match_contours(db, &test_sets, idx).iter().filter(|&&b| b).count();
+        let contours = test_sets.len().max(db.num_contours(idx));
+        let kind = if matched == contours {
+            exact = true;
+            MatchKind::Exact
+        } else {
+            MatchKind::Partial
+        };
+        let score = 0.5 * votes as f32 / num_points.max(1) as f32 + 0.5 * matched as f32 / contours.max(1) as f32;
+        ranked.insert(idx, Ranked { idx, kind, score, votes, matched_contours: matched, margin: 0. });
+    }
+
+    if let (false, Some(threshold)) = (exact, db.fuzzy_threshold()) {
+        let test_contours: Vec<_> = outline.contours().iter().map(canonical_points).collect();
+        for idx in db.fuzzy_index().candidates(&test_contours, threshold) {
+            let Some(dist) = fuzzy_distance(db, &test_contours, idx) else { continue };
+            if dist > threshold {
+                continue;
+            }
+            let score = 1.0 - dist / threshold.max(f32::EPSILON);
+            let votes = ranked.get(&idx).map(|m| m.votes).unwrap_or(0);
+            if ranked.get(&idx).map(|m| m.score < score).unwrap_or(true) {
+                ranked.insert(idx, Ranked { idx, kind: MatchKind::Fuzzy, score, votes, matched_contours: test_contours.len(), margin: 0. });
+            }
+        }
+    }
+
+    let mut ranked: Vec<_> = ranked.into_values().collect();
+    ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+    ranked.truncate(k);
+    for i in 0 .. ranked.len() {
+        let next = ranked.get(i + 1).map(|m| m.score).unwrap_or(0.);
+        ranked[i].margin = ranked[i].score - next;
+    }
+    ranked
+}
+
+/// Marks the contours of entry `idx` that are matched by one of `test_sets`.
+///
+/// Each test contour matches at most one reference contour and vice versa.
+pub(crate) fn match_contours<S: ShapeData + ?Sized>(db: &S, test_sets: &[HashSet<PointKey>], idx: usize) -> Vec<bool> {
+    let rows = test_sets.len();
+    let cols = db.num_contours(idx);
+    let mut cost = vec![1.0; rows * cols];
+    for (t_i, t_s) in test_sets.iter().enumerate() {
+        for r_i in 0 .. cols {
+            if db.contour_matches(idx, r_i, t_s) {
+                cost[t_i * cols + r_i] = 0.0;
+            }
+        }
+    }
+
+    let mut used = vec![false; cols];
+    for (t_i, r_i) in assign(&cost, rows, cols).into_iter().enumerate() {
+        if let Some(r_i) = r_i {
+            if cost[t_i * cols + r_i] == 0.0 {
+                used[r_i] = true;
+            }
+        }
+    }
+    used
+}
+
+/// find the entry with the smallest average frechet distance per contour below `threshold`
+fn fuzzy_match<S: ShapeData + ?Sized>(db: &S, outline: &Outline, threshold: f32, mut report: Option<&mut String>) -> Option<(usize, f32)> {
+    let test_contours: Vec<_> = outline.contours().iter().map(canonical_points).collect();
+    let mut best_entry = None;
+    for idx in db.fuzzy_index().candidates(&test_contours, threshold) {
+        let Some(dist) = fuzzy_distance(db, &test_contours, idx) else { continue };
+        if dist > threshold {
+            continue;
+        }
+        if let Some(report) = report.as_deref_mut() {
+            writeln!(report, "<div><span>{}</span>: {dist}</div>", db.label(idx)).unwrap();
+        }
+        match best_entry {
+            Some((_, d2)) if dist >= d2 => {}
+            _ => best_entry = Some((idx, dist)),
+        }
+    }
+    best_entry
+}
+
+/// average frechet distance per contour between the canonical `test_contours` and the stored outline of entry `idx`
+pub(crate) fn fuzzy_distance<S: ShapeData + ?Sized>(db: &S, test_contours: &[Vec<Vector2F>], idx: usize) -> Option<f32> {
+    let n = test_contours.len();
+    if n == 0 || db.num_contours(idx) != n {
+        return None;
+    }
+    let ref_contours = db.fuzzy_outline(idx);
+    if ref_contours.len() != n {
+        return None;
+    }
+
+    let mut cost = Vec::with_capacity(n * n);
+    for t_c in test_contours.iter() {
+        for r_c in ref_contours.iter() {
+            cost.push(if db.cyclic_fuzzy() {
+                cyclic_frechet_distance_points(t_c, r_c)
+            } else {
+                frechet_distance_points(t_c, r_c)
+            });
+        }
+    }
+    let sum: f32 = assign(&cost, n, n).into_iter().enumerate()
+        .map(|(t_i, r_i)| r_i.map(|r_i| cost[t_i * n + r_i]).unwrap_or(f32::INFINITY))
+        .sum();
+    Some(sum / n as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{db, matrix, outline, shapes};
+    use crate::ShapeDb;
+
+    #[test]
+    fn negative_coordinates() {
+        // a glyph reaching below the baseline and left of the origin, and the same glyph clamped to 0
+        let glyph = outline(&[&[(-300., -200.), (400., -200.), (400., 500.), (-300., 500.)]]);
+        let clamped = outline(&[&[(0., 0.), (400., 0.), (400., 500.), (0., 500.)]]);
+        assert!(points_set(&glyph.contours()[0]).contains(&(-300, -200)));
+
+        let mut db = ShapeDb::new();
+        db.add_outline(&glyph, matrix(), "glyph");
+        db.add_outline(&clamped, matrix(), "clamped");
+        assert_eq!(db.get(&glyph, matrix(), None), Some(&"glyph"));
+        assert_eq!(db.get(&clamped, matrix(), None), Some(&"clamped"));
+    }
+
+    /// label, kind and matched contours of the results, after checking their scores and margins
+    fn ranked<'a>(db: &'a ShapeDb<String>, query: &Outline, k: usize) -> Vec<(&'a str, MatchKind, usize)> {
+        let results = db.get_ranked(query, matrix(), k);
+        assert!(results.len() <= k);
+        for (i, r) in results.iter().enumerate() {
+            assert!((0. ..= 1.).contains(&r.score), "{}", r.score);
+            let next = results.get(i + 1).map(|n| n.score).unwrap_or(0.);
+            assert!(r.score >= next);
+            assert_eq!(r.margin, r.score - next);
+        }
+        results.iter().map(|r| (r.data.as_str(), r.kind, r.matched_contours)).collect()
+    }
+
+    #[test]
+    fn ranking() {
+        let db = db();
+        let square = &shapes()[0].0;
+
+        let exact = ranked(&db, square, 3);
+        assert_eq!(exact[0], ("square", MatchKind::Exact, 1));
+        assert_eq!(exact.len(), 3);
+        assert!(exact[1 ..].iter().all(|r| r.1 == MatchKind::Partial));
+        assert_eq!(db.get_ranked(square, matrix(), 3)[0].score, 1.);
+        assert_eq!(ranked(&db, square, 1), &exact[.. 1]);
+
+        // the outer contour of the frame
+        let outer = outline(&[&[(0., 0.), (600., 0.), (600., 600.), (0., 600.)]]);
+        assert_eq!(ranked(&db, &outer, 3)[0], ("frame", MatchKind::Partial, 1));
+
+        // a slightly distorted square is only found by the fuzzy matcher
+        let distorted = outline(&[&[(0., 0.), (503., 0.), (503., 497.), (0., 500.)]]);
+        assert_eq!(ranked(&db, &distorted, 3)[0], ("square", MatchKind::Fuzzy, 1));
+        let mut db = db;
+        db.set_fuzzy_threshold(None);
+        assert!(ranked(&db, &distorted, 3).iter().all(|r| r.1 == MatchKind::Partial));
+    }
+}
@@ -0,0 +1,515 @@
+//! Database layout that is queried in place, without decoding it.
+//!
+//! Files start with `MAPPED_MAGIC`, the format version as a little endian `u16`,
+//! the length of the postcard encoded `MappedHeader` as a little endian `u32` and the header itself.
+//! The sections listed in the header follow. Each is an array of little endian records of fixed size,
+//! variable length data is referenced by index and count:
+//!
+//! - `cells`: `(x: i32, y: i32, start: u32, len: u32)` sorted by cell, the range of `cell_entries` of a cell of the point index
+//! - `cell_entries`: `u32` entry indices
+//! - `entries`: `(contours: u32, num_contours: u32, outline: u32, outline_len: u32, label: u32, label_len: u32, transform: [f32; 6])`
+//! - `contours`: `(start: u32, len: u32)` range of `keys`
+//! - `keys`: `(x: i32, y: i32)` the point keys of a contour, sorted
+//! - `outlines`: `(start: u32, len: u32)` range of `outline_points`
+//! - `outline_points`: `(x: f32, y: f32)` canonical points of a contour for fuzzy matching
+//! - `labels`: UTF-8 text, `label_len` bytes per entry
+//!
+//! With the `mmap` feature files are memory mapped, so only the pages touched by lookups are read.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use istring::SmallString;
+use pathfinder_content::outline::Outline;
+use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_geometry::vector::Vector2F;
+use serde::{Deserialize, Serialize};
+
+use crate::format::FormatError;
+use crate::hash::{hash_bytes, outline_hash};
+use crate::index::{query_cells, sets_match_sorted, PointKey};
+use crate::lookup::{find, prepare, FuzzyIndex, ShapeData};
+use crate::memo::cache_key;
+use crate::{BuildParams, Entry, Result, ShapeDb};
+
+pub const MAPPED_MAGIC: [u8; 4] = *b"GMMP";
+pub const MAPPED_VERSION: u16 = 1;
+
+const CELL_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 48;
+const RANGE_SIZE: usize = 8;
+const POINT_SIZE: usize = 8;
+
+/// position of a section, relative to the end of the header
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Section {
+    pub offset: u64,
+    /// length in bytes
+    pub len: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MappedHeader {
+    /// hash of the font file the database was built from
+    pub source_hash: u64,
+    pub params: BuildParams,
+    pub cell_size: i32,
+    pub radius: i32,
+    pub num_entries: u64,
+    pub cells: Section,
+    pub cell_entries: Section,
+    pub entries: Section,
+    pub contours: Section,
+    pub keys: Section,
+    pub outlines: Section,
+    pub outline_points: Section,
+    pub labels: Section,
+    /// hash of all sections
+    pub checksum: u64,
+}
+impl MappedHeader {
+    /// sections and their record sizes
+    fn sections(&self) -> [(Section, usize); 8] {
+        [
+            (self.cells, CELL_SIZE),
+            (self.cell_entries, 4),
+            (self.entries, ENTRY_SIZE),
+            (self.contours, RANGE_SIZE),
+            (self.keys, POINT_SIZE),
+            (self.outlines, RANGE_SIZE),
+            (self.outline_points, POINT_SIZE),
+            (self.labels, 1),
+        ]
+    }
+}
+
+/// whether `data` is in the mapped layout
+pub fn is_mapped(data: &[u8]) -> bool {
+    data.starts_with(&MAPPED_MAGIC)
+}
+
+/// whether the file at `path` is in the mapped layout, only reads its first bytes
+pub fn is_mapped_file(path: &Path) -> std::io::Result<bool> {
+    let mut magic = [0; 4];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == MAPPED_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Decode the header of a file in the mapped layout, `data` may be only the start of the file.
+///
+/// Also returns the position of the first section. The sections are not checked, see `read_mapped_header`.
+pub fn parse_mapped_header(data: &[u8]) -> Result<(MappedHeader, usize), FormatError> {
+    let Some(rest) = data.strip_prefix(&MAPPED_MAGIC) else {
+        return Err(FormatError::InvalidMagic);
+    };
+    if rest.len() < 6 {
+        return Err(FormatError::Truncated);
+    }
+    let version = u16::from_le_bytes([rest[0], rest[1]]);
+    if version != MAPPED_VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+    let header_len = u32::from_le_bytes([rest[2], rest[3], rest[4], rest[5]]) as usize;
+    let header_data = rest[6 ..].get(.. header_len).ok_or(FormatError::Truncated)?;
+    let header: MappedHeader = postcard::from_bytes(header_data)?;
+    let data_start = MAPPED_MAGIC.len() + 6 + header_len;
+    Ok((header, data_start))
+}
+
+/// the header of a file in the mapped layout and the position of its first section
+pub fn read_mapped_header(data: &[u8]) -> Result<(MappedHeader, usize), FormatError> {
+    let (header, data_start) = parse_mapped_header(data)?;
+    let data_len = (data.len() - data_start) as u64;
+    for (section, size) in header.sections() {
+        let in_bounds = section.offset.checked_add(section.len).is_some_and(|end| end <= data_len);
+        if !in_bounds || section.len % size as u64 != 0 {
+            return Err(FormatError::Truncated);
+        }
+    }
+    if header.entries.len != header.num_entries * ENTRY_SIZE as u64 {
+        return Err(FormatError::Truncated);
+    }
+    Ok((header, data_start))
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+fn put_i32(out: &mut Vec<u8>, v: i32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+fn put_f32(out: &mut Vec<u8>, v: f32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+impl<I: Display> ShapeDb<I> {
+    /// encode the database in the mapped layout, `source_hash` identifies the font it was built from
+    pub fn to_mapped_bytes(&self, source_hash: u64) -> Result<Vec<u8>, FormatError> {
+        let mut cells: Vec<_> = self.points.cells().collect();
+        cells.sort_by_key(|&(cell, _)| cell);
+        let mut cell_data = vec![];
+        let mut cell_entries = vec![];
+        for (cell, list) in cells {
+            put_i32(&mut cell_data, cell.0);
+            put_i32(&mut cell_data, cell.1);
+            put_u32(&mut cell_data, (cell_entries.len() / 4) as u32);
+            put_u32(&mut cell_data, list.len() as u32);
+            for &idx in list {
+                put_u32(&mut cell_entries, idx as u32);
+            }
+        }
+
+        let (mut entries, mut contours, mut keys, mut outlines, mut outline_points, mut labels) = (vec![], vec![], vec![], vec![], vec![], vec![]);
+        for e in self.entries.iter() {
+            put_u32(&mut entries, (contours.len() / RANGE_SIZE) as u32);
+            put_u32(&mut entries, e.contour_sets.len() as u32);
+            for set in e.contour_sets.iter() {
+                let mut points: Vec<PointKey> = set.iter().copied().collect();
+                points.sort();
+                put_u32(&mut contours, (keys.len() / POINT_SIZE) as u32);
+                put_u32(&mut contours, points.len() as u32);
+                for (x, y) in points {
+                    put_i32(&mut keys, x);
+                    put_i32(&mut keys, y);
+                }
+            }
+
+            put_u32(&mut entries, (outlines.len() / RANGE_SIZE) as u32);
+            put_u32(&mut entries, e.outline.len() as u32);
+            for contour in e.outline.iter() {
+                put_u32(&mut outlines, (outline_points.len() / POINT_SIZE) as u32);
+                put_u32(&mut outlines, contour.len() as u32);
+                for &(x, y) in contour {
+                    put_f32(&mut outline_points, x);
+                    put_f32(&mut outline_points, y);
+                }
+            }
+
+            let label = e.data.to_string();
+            put_u32(&mut entries, labels.len() as u32);
+            put_u32(&mut entries, label.len() as u32);
+            labels.extend_from_slice(label.as_bytes());
+
+            for v in e.transform {
+                put_f32(&mut entries, v);
+            }
+        }
+
+        let mut data = vec![];
+        let mut section = |bytes: Vec<u8>| {
+            let s = Section { offset: data.len() as u64, len: bytes.len() as u64 };
+            data.extend(bytes);
+            s
+        };
+        let mut header = MappedHeader {
+            source_hash,
+            params: self.params,
+            cell_size: self.points.cell_size(),
+            radius: self.points.radius(),
+            num_entries: self.entries.len() as u64,
+            cells: section(cell_data),
+            cell_entries: section(cell_entries),
+            entries: section(entries),
+            contours: section(contours),
+            keys: section(keys),
+            outlines: section(outlines),
+            outline_points: section(outline_points),
+            labels: section(labels),
+            checksum: 0,
+        };
+        header.checksum = hash_bytes(&data);
+
+        let header_data = postcard::to_allocvec(&header)?;
+        let mut out = Vec::with_capacity(MAPPED_MAGIC.len() + 6 + header_data.len() + data.len());
+        out.extend_from_slice(&MAPPED_MAGIC);
+        out.extend_from_slice(&MAPPED_VERSION.to_le_bytes());
+        put_u32(&mut out, header_data.len() as u32);
+        out.extend_from_slice(&header_data);
+        out.extend_from_slice(&data);
+        Ok(out)
+    }
+}
+
+enum Bytes {
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+    Owned(Vec<u8>),
+}
+impl Deref for Bytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(feature = "mmap")]
+            Bytes::Mapped(map) => map,
+            Bytes::Owned(data) => data,
+        }
+    }
+}
+
+fn u32_at(record: &[u8], field: usize) -> u32 {
+    record.get(4 * field .. 4 * field + 4).map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+fn i32_at(record: &[u8], field: usize) -> i32 {
+    u32_at(record, field) as i32
+}
+fn f32_at(record: &[u8], field: usize) -> f32 {
+    f32::from_bits(u32_at(record, field))
+}
+
+/// a database in the mapped layout
+///
+/// Lookups read the records they need, nothing is decoded up front.
+pub struct MappedDb {
+    bytes: Bytes,
+    header: MappedHeader,
+    data_start: usize,
+    fuzzy_threshold: Option<f32>,
+    cyclic_fuzzy: bool,
+    fuzzy_index: OnceLock<FuzzyIndex>,
+}
+impl MappedDb {
+    /// Open the file at `path`, memory mapped with the `mmap` feature.
+    ///
+    /// The checksum is not verified, as that would read the whole file (see `verify`).
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: database files are only ever replaced through `store::replace_file`,
+        // so the mapped file is not modified while it is mapped
+        #[cfg(feature = "mmap")]
+        let bytes = Bytes::Mapped(unsafe { memmap2::Mmap::map(&file)? });
+        #[cfg(not(feature = "mmap"))]
+        let bytes = {
+            let mut data = vec![];
+            (&file).read_to_end(&mut data)?;
+            Bytes::Owned(data)
+        };
+        Ok(MappedDb::new(bytes)?)
+    }
+    /// use data that is already in memory, the checksum is verified
+    pub fn from_vec(data: Vec<u8>) -> Result<Self, FormatError> {
+        let db = MappedDb::new(Bytes::Owned(data))?;
+        db.verify()?;
+        Ok(db)
+    }
+    fn new(bytes: Bytes) -> Result<Self, FormatError> {
+        let (header, data_start) = read_mapped_header(&bytes)?;
+        Ok(MappedDb { bytes, header, data_start, fuzzy_threshold: crate::default_fuzzy_threshold(), cyclic_fuzzy: false, fuzzy_index: OnceLock::new() })
+    }
+    /// compare the checksum in the header with the sections
+    pub fn verify(&self) -> Result<(), FormatError> {
+        let checksum = hash_bytes(&self.bytes[self.data_start ..]);
+        if checksum != self.header.checksum {
+            return Err(FormatError::Checksum { expected: self.header.checksum, found: checksum });
+        }
+        Ok(())
+    }
+    pub fn header(&self) -> &MappedHeader {
+        &self.header
+    }
+    pub fn len(&self) -> usize {
+        self.header.num_entries as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.header.num_entries == 0
+    }
+    /// see `ShapeDb::set_fuzzy_threshold`
+    pub fn set_fuzzy_threshold(&mut self, threshold: Option<f32>) {
+        self.fuzzy_threshold = threshold;
+    }
+    /// see `ShapeDb::set_cyclic_fuzzy`
+    pub fn set_cyclic_fuzzy(&mut self, cyclic: bool) {
+        self.cyclic_fuzzy = cyclic;
+    }
+    pub fn get(&self, outline: &Outline, font_matrix: Transform2F, report: Option<&mut String>) -> Option<&str> {
+        find(self, outline, font_matrix, report).map(|idx| self.label(idx))
+    }
+    /// see `ShapeDb::cache_key`
+    pub fn cache_key(&self) -> u64 {
+        cache_key(MAPPED_VERSION, self.header.checksum, self.fuzzy_threshold, self.cyclic_fuzzy)
+    }
+    /// see `ShapeDb::outline_hash`
+    pub fn outline_hash(&self, outline: &Outline, font_matrix: Transform2F) -> u64 {
+        let (outline, _) = prepare(&self.header.params, outline, font_matrix);
+        outline_hash(&outline)
+    }
+    /// decode all entries
+    pub fn to_shape_db(&self) -> ShapeDb<SmallString> {
+        let mut db = ShapeDb::with_params(self.header.params);
+        db.fuzzy_threshold = self.fuzzy_threshold;
+        db.cyclic_fuzzy = self.cyclic_fuzzy;
+        for idx in 0 .. self.len() {
+            let entry = self.entry(idx);
+            let mut transform = [0.; 6];
+            for (i, v) in transform.iter_mut().enumerate() {
+                *v = f32_at(entry, 6 + i);
+            }
+            db.push_entry(Entry {
+                contour_sets: (0 .. self.num_contours(idx)).map(|c| self.contour_keys(idx, c).into_iter().collect()).collect(),
+                outline: self.fuzzy_outline(idx).into_iter().map(|c| c.into_iter().map(|p| (p.x(), p.y())).collect()).collect(),
+                transform,
+                data: self.label(idx).into(),
+            });
+        }
+        db
+    }
+
+    fn section(&self, section: Section) -> &[u8] {
+        // checked in `read_mapped_header`
+        let start = self.data_start + section.offset as usize;
+        &self.bytes[start .. start + section.len as usize]
+    }
+    /// records `start .. start + len` of `section`, empty if out of bounds
+    fn records(&self, section: Section, size: usize, start: u32, len: u32) -> &[u8] {
+        let range = (start as usize).checked_mul(size)
+            .and_then(|a| Some(a .. (start as usize).checked_add(len as usize)?.checked_mul(size)?));
+        range.and_then(|r| self.section(section).get(r)).unwrap_or(&[])
+    }
+    fn entry(&self, idx: usize) -> &[u8] {
+        self.records(self.header.entries, ENTRY_SIZE, idx as u32, 1)
+    }
+    fn contour_keys(&self, idx: usize, contour: usize) -> Vec<PointKey> {
+        let entry = self.entry(idx);
+        let range = self.records(self.header.contours, RANGE_SIZE, u32_at(entry, 0).wrapping_add(contour as u32), 1);
+        self.records(self.header.keys, POINT_SIZE, u32_at(range, 0), u32_at(range, 1))
+            .chunks_exact(POINT_SIZE)
+            .map(|p| (i32_at(p, 0), i32_at(p, 1)))
+            .collect()
+    }
+    /// the entries in `cell` of the point index
+    fn cell_entries(&self, cell: PointKey) -> Option<impl Iterator<Item=usize> + '_> {
+        let cells = self.section(self.header.cells);
+        let n = cells.len() / CELL_SIZE;
+        let record = |i: usize| &cells[i * CELL_SIZE .. (i + 1) * CELL_SIZE];
+        let (mut lo, mut hi) = (0, n);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let r = record(mid);
+            match (i32_at(r, 0), i32_at(r, 1)).cmp(&cell) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => {
+                    let list = self.records(self.header.cell_entries, 4, u32_at(r, 2), u32_at(r, 3));
+                    return Some(list.chunks_exact(4).map(|b| u32_at(b, 0) as usize));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl ShapeData for MappedDb {
+    type Label<'a> = &'a str;
+
+    fn params(&self) -> &BuildParams {
+        &self.header.params
+    }
+    fn fuzzy_threshold(&self) -> Option<f32> {
+        self.fuzzy_threshold
+    }
+    fn cyclic_fuzzy(&self) -> bool {
+        self.cyclic_fuzzy
+    }
+    fn num_entries(&self) -> usize {
+        self.len()
+    }
+    fn label(&self, idx: usize) -> &str {
+        let entry = self.entry(idx);
+        let bytes = self.records(self.header.labels, 1, u32_at(entry, 4), u32_at(entry, 5));
+        std::str::from_utf8(bytes).unwrap_or("")
+    }
+    fn query_points(&self, key: PointKey, f: &mut dyn FnMut(usize)) {
+        query_cells(self.header.cell_size, self.header.radius, key, |cell| self.cell_entries(cell), f)
+    }
+    fn num_contours(&self, idx: usize) -> usize {
+        u32_at(self.entry(idx), 1) as usize
+    }
+    fn contour_matches(&self, idx: usize, contour: usize, test: &HashSet<PointKey>) -> bool {
+        sets_match_sorted(test, &self.contour_keys(idx, contour), self.header.params.tolerance)
+    }
+    fn fuzzy_outline(&self, idx: usize) -> Vec<Vec<Vector2F>> {
+        let entry = self.entry(idx);
+        self.records(self.header.outlines, RANGE_SIZE, u32_at(entry, 2), u32_at(entry, 3))
+            .chunks_exact(RANGE_SIZE)
+            .map(|range| {
+                self.records(self.header.outline_points, POINT_SIZE, u32_at(range, 0), u32_at(range, 1))
+                    .chunks_exact(POINT_SIZE)
+                    .map(|p| Vector2F::new(f32_at(p, 0), f32_at(p, 1)))
+                    .collect()
+            })
+            .collect()
+    }
+    fn fuzzy_index(&self) -> &FuzzyIndex {
+        self.fuzzy_index.get_or_init(|| FuzzyIndex::build(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{db, matrix, outline, shapes};
+
+    /// outlines to look up: the shapes, slightly moved copies and shapes that are not in the database
+    fn queries() -> Vec<Outline> {
+        let mut queries: Vec<_> = shapes().into_iter().map(|(o, _)| o).collect();
+        queries.push(outline(&[&[(0., 0.), (501., 0.), (501., 499.), (1., 500.)]]));
+        queries.push(outline(&[&[(0., 0.), (600., 2.), (298., 700.)]]));
+        queries.push(outline(&[&[(0., 0.), (900., 0.), (0., 200.)]]));
+        queries.push(outline(&[&[(0., 0.), (100., 0.), (100., 100.)], &[(300., 0.), (400., 0.), (400., 100.)]]));
+        queries
+    }
+
+    #[test]
+    fn round_trip() {
+        let db = db();
+        let data = db.to_mapped_bytes(42).unwrap();
+        assert!(is_mapped(&data));
+        let mapped = MappedDb::from_vec(data).unwrap();
+        assert_eq!(mapped.header().source_hash, 42);
+        assert_eq!(mapped.header().params, *db.params());
+        assert_eq!(mapped.len(), db.len());
+
+        let decoded = mapped.to_shape_db();
+        for (outline, label) in shapes() {
+            assert_eq!(mapped.get(&outline, matrix(), None), Some(label));
+            assert_eq!(decoded.get(&outline, matrix(), None).map(|s| &**s), Some(label));
+            assert_eq!(mapped.outline_hash(&outline, matrix()), db.outline_hash(&outline, matrix()));
+        }
+    }
+
+    #[test]
+    fn lookup_parity() {
+        for threshold in [None, Some(0.05)] {
+            let mut db = db();
+            db.set_fuzzy_threshold(threshold);
+            let mut mapped = MappedDb::from_vec(db.to_mapped_bytes(0).unwrap()).unwrap();
+            mapped.set_fuzzy_threshold(threshold);
+            for query in queries() {
+                assert_eq!(mapped.get(&query, matrix(), None), db.get(&query, matrix(), None).map(|s| s.as_str()));
+            }
+        }
+    }
+
+    #[test]
+    fn reject_damaged() {
+        let mut data = db().to_mapped_bytes(0).unwrap();
+        *data.last_mut().unwrap() ^= 0xFF;
+        assert!(matches!(MappedDb::from_vec(data.clone()), Err(FormatError::Checksum { .. })));
+        // without verifying, a damaged file still only gives wrong results
+        let mapped = MappedDb::new(Bytes::Owned(data.clone())).unwrap();
+        for query in queries() {
+            mapped.get(&query, matrix(), None);
+        }
+
+        data.truncate(data.len() - 1);
+        assert!(MappedDb::from_vec(data).is_err());
+    }
+}
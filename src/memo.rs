@@ -0,0 +1,282 @@
+//! Cache of lookup results, keyed by database and outline hash.
+//!
+//! The same glyphs appear in many documents. Results are keyed by `ShapeDb::cache_key`
+//! and `ShapeDb::outline_hash`, so a cache stays valid across runs as long as the databases do not change.
+//! The result is the index of the matching entry, so lookups in the global index are remembered
+//! like lookups in a single database (see `GlobalIndex::cache_key`).
+//! A cache file starts with `MEMO_MAGIC`, the format version as a little endian `u16`
+//! and the postcard encoded entries.
+
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+use istring::SmallString;
+use pathfinder_content::outline::Outline;
+use pathfinder_geometry::transform2d::Transform2F;
+
+use crate::cache::{read, write};
+use crate::format::FormatError;
+use crate::hash::{outline_hash, Fnv1a};
+use crate::lookup::{find_prepared, prepare, ShapeData};
+use crate::store::replace_file;
+use crate::{ReferenceDb, Result};
+
+pub const MEMO_MAGIC: [u8; 4] = *b"GMRC";
+pub const MEMO_VERSION: u16 = 2;
+
+/// Identifies the results of lookups in a database with the layout `version`
+/// and the content hash `content_hash`, searched with the given fuzzy matching settings.
+pub(crate) fn cache_key(version: u16, content_hash: u64, fuzzy_threshold: Option<f32>, cyclic_fuzzy: bool) -> u64 {
+    let mut h = Fnv1a::default();
+    h.write(&version.to_le_bytes());
+    h.write(&content_hash.to_le_bytes());
+    h.write(&fuzzy_threshold.map_or(u32::MAX, f32::to_bits).to_le_bytes());
+    h.write(&[cyclic_fuzzy as u8]);
+    h.finish()
+}
+
+/// (database, outline) → index of the matching entry, `None` if the outline has no match
+type MemoEntries = Vec<(u64, u64, Option<u32>)>;
+
+/// number of results a `ResultCache` keeps by default
+pub const DEFAULT_MAX_ENTRIES: usize = 1 << 20;
+
+struct Memo {
+    entry: Option<u32>,
+    /// value of `ResultCache::clock` when the result was last used
+    last_used: AtomicU64,
+}
+
+pub struct ResultCache {
+    map: RwLock<HashMap<(u64, u64), Memo>>,
+    /// file the cache is saved to
+    path: Option<PathBuf>,
+    /// entries were added since the cache was loaded or saved
+    dirty: AtomicBool,
+    clock: AtomicU64,
+    max_entries: Option<usize>,
+}
+impl Default for ResultCache {
+    fn default() -> Self {
+        ResultCache {
+            map: Default::default(),
+            path: None,
+            dirty: AtomicBool::new(false),
+            clock: AtomicU64::new(0),
+            max_entries: Some(DEFAULT_MAX_ENTRIES),
+        }
+    }
+}
+impl ResultCache {
+    /// a cache that is only kept in memory
+    pub fn new() -> Self {
+        ResultCache::default()
+    }
+    /// Use the cache file at `path`, loading it if it exists.
+    ///
+    /// The cache is written back by `save`.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut map = HashMap::new();
+        if path.is_file() {
+            let entries = read_entries(&std::fs::read(&path)?)?;
+            map.extend(entries.into_iter().map(|(db, outline, entry)| ((db, outline), Memo { entry, last_used: AtomicU64::new(0) })));
+        }
+        let mut cache = ResultCache { map: RwLock::new(map), path: Some(path), ..ResultCache::default() };
+        evict(cache.map.get_mut().unwrap_or_else(PoisonError::into_inner), cache.max_entries);
+        Ok(cache)
+    }
+    /// Keep at most `max` results, `None` for no limit. The least recently used results are dropped first.
+    pub fn set_max_entries(&mut self, max: Option<usize>) {
+        self.max_entries = max;
+        evict(self.map.get_mut().unwrap_or_else(PoisonError::into_inner), max);
+    }
+    pub fn max_entries(&self) -> Option<usize> {
+        self.max_entries
+    }
+    pub fn len(&self) -> usize {
+        read(&self.map).len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// the index of the entry of database `db` that matches the outline `outline`, if known
+    pub fn get(&self, db: u64, outline: u64) -> Option<Option<u32>> {
+        let map = read(&self.map);
+        let memo = map.get(&(db, outline))?;
+        memo.last_used.store(self.clock.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+        Some(memo.entry)
+    }
+    pub fn insert(&self, db: u64, outline: u64, entry: Option<u32>) {
+        let memo = Memo { entry, last_used: AtomicU64::new(self.clock.fetch_add(1, Ordering::Relaxed)) };
+        let mut map = write(&self.map);
+        map.insert((db, outline), memo);
+        evict(&mut map, self.max_entries);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+    pub fn clear(&self) {
+        write(&self.map).clear();
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+    /// Find the entry of `db` matching `outline`, answering from the cache if possible.
+    ///
+    /// `key` identifies the results of `db` (see `ShapeDb::cache_key`), without one `db` is always searched.
+    pub(crate) fn find<S: ShapeData + ?Sized>(&self, db: &S, key: Option<u64>, outline: &Outline, font_matrix: Transform2F) -> Option<usize> {
+        let (outline, _) = prepare(db.params(), outline, font_matrix);
+        let Some(key) = key else {
+            return find_prepared(db, &outline, None);
+        };
+        let hash = outline_hash(&outline);
+        match self.get(key, hash) {
+            Some(None) => return None,
+            // a damaged cache file must not make the lookup fail
+            Some(Some(idx)) if (idx as usize) < db.num_entries() => return Some(idx as usize),
+            _ => {}
+        }
+        let idx = find_prepared(db, &outline, None);
+        self.insert(key, hash, idx.map(|idx| idx as u32));
+        idx
+    }
+    /// Look up `outline` in `db`, answering from the cache if possible.
+    ///
+    /// Databases that were not loaded from a file have no cache key and are always searched.
+    pub fn get_or_lookup(&self, db: &ReferenceDb, outline: &Outline, font_matrix: Transform2F) -> Option<SmallString> {
+        self.find(db, db.cache_key(), outline, font_matrix).map(|idx| db.label(idx).into())
+    }
+    /// write the cache to its file if it was changed, does nothing for caches kept in memory
+    pub fn save(&self) -> Result<()> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let result = self.write_file(path);
+        if result.is_err() {
+            // the changes are still unsaved
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+    fn write_file(&self, path: &Path) -> Result<()> {
+        let entries: MemoEntries = read(&self.map).iter()
+            .map(|(&(db, outline), memo)| (db, outline, memo.entry))
+            .collect();
+        let mut data = Vec::with_capacity(entries.len() * 24);
+        data.extend_from_slice(&MEMO_MAGIC);
+        data.extend_from_slice(&MEMO_VERSION.to_le_bytes());
+        let data = postcard::to_extend(&entries, data).map_err(FormatError::from)?;
+
+        // an interrupted save does not lose the old cache
+        replace_file(path, &data)
+    }
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+/// Drop the least recently used results until at most `max` are left.
+///
+/// Drops an eighth more than needed, so that not every insert has to sort the results.
+fn evict(map: &mut HashMap<(u64, u64), Memo>, max: Option<usize>) {
+    let Some(max) = max else { return };
+    if map.len() <= max {
+        return;
+    }
+    let keep = max - max / 8;
+    let mut lru: Vec<_> = map.iter().map(|(&key, memo)| (memo.last_used.load(Ordering::Relaxed), key)).collect();
+    lru.sort_unstable();
+    for &(_, key) in &lru[.. map.len() - keep] {
+        map.remove(&key);
+    }
+}
+
+fn read_entries(data: &[u8]) -> Result<MemoEntries, FormatError> {
+    let Some(rest) = data.strip_prefix(&MEMO_MAGIC) else {
+        return Err(FormatError::InvalidMagic);
+    };
+    if rest.len() < 2 {
+        return Err(FormatError::Truncated);
+    }
+    let version = u16::from_le_bytes([rest[0], rest[1]]);
+    if version != MEMO_VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+    Ok(postcard::from_bytes(&rest[2 ..])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{db, matrix, shapes, temp_path};
+    use crate::{Error, ShapeDb};
+
+    #[test]
+    fn lookup() {
+        let db = ShapeDb::<String>::from_bytes(&db().to_bytes(0).unwrap()).unwrap();
+        let key = db.cache_key();
+        assert!(key.is_some());
+        let square = &shapes()[0].0;
+        let hash = outline_hash(&prepare(db.params(), square, matrix()).0);
+
+        let cache = ResultCache::new();
+        assert_eq!(cache.find(&db, None, square, matrix()), Some(0));
+        assert!(cache.is_empty());
+        assert_eq!(cache.find(&db, key, square, matrix()), Some(0));
+        assert_eq!(cache.get(key.unwrap(), hash), Some(Some(0)));
+
+        // answered from the cache
+        cache.insert(key.unwrap(), hash, Some(2));
+        assert_eq!(cache.find(&db, key, square, matrix()), Some(2));
+        // a damaged index is searched again
+        cache.insert(key.unwrap(), hash, Some(99));
+        assert_eq!(cache.find(&db, key, square, matrix()), Some(0));
+        assert_eq!(cache.get(key.unwrap(), hash), Some(Some(0)));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn eviction() {
+        let mut cache = ResultCache::new();
+        cache.set_max_entries(Some(8));
+        for i in 0 .. 8 {
+            cache.insert(1, i, Some(i as u32));
+        }
+        assert_eq!(cache.get(1, 0), Some(Some(0)));
+        cache.insert(1, 8, None);
+        // an eighth more than needed is dropped, least recently used first
+        assert_eq!(cache.len(), 7);
+        assert_eq!(cache.get(1, 1), None);
+        assert_eq!(cache.get(1, 2), None);
+        assert_eq!(cache.get(1, 0), Some(Some(0)));
+        assert_eq!(cache.get(1, 8), Some(None));
+    }
+
+    #[test]
+    fn save_and_open() {
+        let path = temp_path("memo");
+        let _ = std::fs::remove_file(&path);
+        let cache = ResultCache::open(&path).unwrap();
+        assert!(cache.is_empty());
+        cache.insert(1, 2, Some(3));
+        cache.insert(1, 3, None);
+        cache.save().unwrap();
+
+        let cache = ResultCache::open(&path).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(1, 2), Some(Some(3)));
+        assert_eq!(cache.get(1, 3), Some(None));
+
+        std::fs::write(&path, b"not a cache").unwrap();
+        assert!(matches!(ResultCache::open(&path), Err(Error::Database(FormatError::InvalidMagic))));
+        let mut newer = MEMO_MAGIC.to_vec();
+        newer.extend_from_slice(&(MEMO_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, newer).unwrap();
+        assert!(matches!(ResultCache::open(&path), Err(Error::Database(FormatError::UnsupportedVersion(_)))));
+        std::fs::remove_file(path).unwrap();
+    }
+}
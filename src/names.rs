@@ -0,0 +1,207 @@
+//! Normalization of PostScript font names.
+//!
+//! Embedded fonts are named in many ways for the same font:
+//! `ABCDEF+Arial,Bold`, `Arial-BoldMT` and `Arial Bold` all refer to the same font.
+//! Names are reduced to a canonical `Family-Style` form to compare them.
+
+/// remove a subset tag (six upper case letters followed by `+`)
+pub fn strip_subset_tag(name: &str) -> &str {
+    match name.split_once('+') {
+        Some((tag, rest)) if tag.len() == 6 && tag.bytes().all(|b| b.is_ascii_uppercase()) => rest,
+        _ => name,
+    }
+}
+
+/// style words that are split off the end of a family name without separator
+const STYLE_SUFFIXES: &[&str] = &["BoldItalic", "BoldOblique", "Bold", "Italic", "Oblique", "Regular"];
+
+/// vendor suffixes that do not change the identity of a font
+const VENDOR_SUFFIXES: &[&str] = &["PSMT", "MT", "PS"];
+
+/// weights that are abbreviated with a trailing `It` for italic, as in `BoldIt`
+const WEIGHT_STYLES: &[&str] = &[
+    "", "thin", "hairline", "extralight", "ultralight", "light", "book", "regular", "roman", "medium",
+    "semibold", "demibold", "demi", "bold", "extrabold", "ultrabold", "heavy", "black",
+];
+
+/// styles that are the same as no style
+const REGULAR_STYLES: &[&str] = &["", "regular", "roman", "normal", "book", "plain"];
+
+/// families that are metrically compatible and used interchangeably
+const FAMILY_ALIASES: &[(&str, &str)] = &[
+    ("Arial", "Helvetica"),
+    ("TimesNewRoman", "Times"),
+    ("CourierNew", "Courier"),
+];
+
+fn strip_vendor_suffix(s: &str) -> &str {
+    for suffix in VENDOR_SUFFIXES {
+        if let Some(rest) = s.strip_suffix(suffix) {
+            if !rest.is_empty() {
+                return rest;
+            }
+        }
+    }
+    s
+}
+
+fn canonical_style(style: &str) -> String {
+    let mut s = style.to_ascii_lowercase();
+    let mut italic = false;
+    for word in ["italic", "oblique", "ital"] {
+        if let Some(pos) = s.find(word) {
+            s.replace_range(pos .. pos + word.len(), "");
+            italic = true;
+        }
+    }
+    if !italic {
+        if let Some(weight) = s.strip_suffix("it").filter(|w| WEIGHT_STYLES.contains(w)) {
+            s.truncate(weight.len());
+            italic = true;
+        }
+    }
+    let mut out = String::new();
+    if !REGULAR_STYLES.contains(&s.as_str()) {
+        let mut chars = s.chars();
+        if let Some(first) = chars.next() {
+            out.push(first.to_ascii_uppercase());
+            out.extend(chars);
+        }
+    }
+    if italic {
+        out.push_str("Italic");
+    }
+    out
+}
+
+/// a font name split into family and style
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsName {
+    pub family: String,
+    /// empty for the regular style
+    pub style: String,
+}
+impl PsName {
+    /// Split `name` at the first comma, or else at the last hyphen, as family names may contain hyphens.
+    /// Hyphens that remain in the family are dropped.
+    pub fn parse(name: &str) -> PsName {
+        let name = strip_subset_tag(name.trim());
+        let (family, style) = match name.split_once(',').or_else(|| name.rsplit_once('-')) {
+            Some(parts) => parts,
+            None => (name, ""),
+        };
+        let mut family: String = family.chars().filter(|&c| !c.is_whitespace() && c != '-').collect();
+        let mut style: String = style.chars().filter(|c| !c.is_whitespace()).collect();
+
+        if style.is_empty() {
+            family = strip_vendor_suffix(&family).into();
+            for suffix in STYLE_SUFFIXES {
+                if let Some(rest) = family.strip_suffix(suffix) {
+                    if !rest.is_empty() {
+                        style = suffix.to_string();
+                        family.truncate(rest.len());
+                        break;
+                    }
+                }
+            }
+        }
+        let family = strip_vendor_suffix(&family).into();
+        let style = canonical_style(strip_vendor_suffix(&style));
+        PsName { family, style }
+    }
+    /// `Family` or `Family-Style`
+    pub fn canonical(&self) -> String {
+        if self.style.is_empty() {
+            self.family.clone()
+        } else {
+            format!("{}-{}", self.family, self.style)
+        }
+    }
+    /// the same style in families that are aliases of this one
+    pub fn aliases(&self) -> impl Iterator<Item=PsName> + '_ {
+        FAMILY_ALIASES.iter().filter_map(move |&(a, b)| {
+            if self.family == a {
+                Some(b)
+            } else if self.family == b {
+                Some(a)
+            } else {
+                None
+            }
+        }).map(move |family| PsName { family: family.into(), style: self.style.clone() })
+    }
+}
+
+/// canonical form of a PostScript name
+pub fn normalize_ps_name(name: &str) -> String {
+    PsName::parse(name).canonical()
+}
+
+/// Names to try when looking up `name`, in order of preference.
+///
+/// The name itself, without subset tag, its canonical form and the canonical forms of its aliases.
+pub fn lookup_names(name: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    let mut push = |n: String| {
+        if !n.is_empty() && !names.contains(&n) {
+            names.push(n);
+        }
+    };
+    push(name.into());
+    push(strip_subset_tag(name).into());
+    let parsed = PsName::parse(name);
+    push(parsed.canonical());
+    for alias in parsed.aliases() {
+        push(alias.canonical());
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str) -> (String, String) {
+        let n = PsName::parse(name);
+        (n.family, n.style)
+    }
+
+    #[test]
+    fn parse_separators() {
+        assert_eq!(parse("Arial-BoldMT"), ("Arial".into(), "Bold".into()));
+        assert_eq!(parse("ABCDEF+Arial,Bold"), ("Arial".into(), "Bold".into()));
+        assert_eq!(parse("Arial Bold"), ("Arial".into(), "Bold".into()));
+        assert_eq!(parse("TimesNewRomanPSMT"), ("TimesNewRoman".into(), "".into()));
+        assert_eq!(parse("Arial,BoldItalic"), ("Arial".into(), "BoldItalic".into()));
+    }
+
+    #[test]
+    fn parse_hyphenated_family() {
+        assert_eq!(parse("Noto-Sans-Bold"), ("NotoSans".into(), "Bold".into()));
+        assert_eq!(parse("Noto-Sans,Bold"), ("NotoSans".into(), "Bold".into()));
+        assert_eq!(normalize_ps_name("Noto-Sans-Bold"), normalize_ps_name("NotoSans-Bold"));
+    }
+
+    #[test]
+    fn italic_abbreviation() {
+        assert_eq!(canonical_style("BoldIt"), "BoldItalic");
+        assert_eq!(canonical_style("It"), "Italic");
+        assert_eq!(canonical_style("SemiboldIt"), "SemiboldItalic");
+        assert_eq!(canonical_style("Oblique"), "Italic");
+        // words ending in "it" that are not an abbreviation
+        assert_eq!(canonical_style("Wit"), "Wit");
+        assert_eq!(canonical_style("Kit"), "Kit");
+    }
+
+    #[test]
+    fn regular_styles() {
+        assert_eq!(normalize_ps_name("Helvetica-Roman"), "Helvetica");
+        assert_eq!(normalize_ps_name("Helvetica"), "Helvetica");
+        assert_eq!(normalize_ps_name("Helvetica-Book"), "Helvetica");
+    }
+
+    #[test]
+    fn lookup_aliases() {
+        let names = lookup_names("ABCDEF+Arial-BoldMT");
+        assert_eq!(names, ["ABCDEF+Arial-BoldMT", "Arial-BoldMT", "Arial-Bold", "Helvetica-Bold"]);
+    }
+}
@@ -0,0 +1,50 @@
+use pathfinder_content::outline::Outline;
+use pathfinder_geometry::transform2d::Transform2F;
+use serde::{Deserialize, Serialize};
+
+/// size of the em square normalized outlines are mapped to
+pub const EM_SIZE: f32 = 1000.;
+
+/// how outlines are brought into a common coordinate system before they are indexed or looked up
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Normalization {
+    /// use the coordinates as stored in the font
+    None,
+    /// map font units to an em of `EM_SIZE` units using the font matrix
+    #[default]
+    UnitsPerEm,
+    /// additionally move the bounding box to the origin and scale it to `EM_SIZE`
+    BoundingBox,
+}
+
+/// returns the transformation from font units into the normalized coordinate system
+pub fn normalize_transform(outline: &Outline, font_matrix: Transform2F, mode: Normalization) -> Transform2F {
+    match mode {
+        Normalization::None => Transform2F::default(),
+        Normalization::UnitsPerEm => Transform2F::from_scale(EM_SIZE) * font_matrix,
+        Normalization::BoundingBox => {
+            let em = Transform2F::from_scale(EM_SIZE) * font_matrix;
+            let b = outline.clone().transformed(&em).bounds();
+            let size = b.width().max(b.height());
+            if size <= 0. {
+                return em;
+            }
+            Transform2F::from_scale(EM_SIZE / size) * Transform2F::from_translation(-b.origin()) * em
+        }
+    }
+}
+
+/// transform `outline` into the normalized coordinate system
+///
+/// Returns the transformed outline and the transform that was applied.
+pub fn normalize(outline: &Outline, font_matrix: Transform2F, mode: Normalization) -> (Outline, Transform2F) {
+    let tr = normalize_transform(outline, font_matrix, mode);
+    if tr.is_identity() {
+        return (outline.clone(), tr);
+    }
+    (outline.clone().transformed(&tr), tr)
+}
+
+pub(crate) fn transform_to_array(tr: Transform2F) -> [f32; 6] {
+    [tr.m11(), tr.m12(), tr.m21(), tr.m22(), tr.m31(), tr.m32()]
+}
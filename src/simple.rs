@@ -0,0 +1,80 @@
+//! Simple (8 bit) fonts in PDFs.
+//!
+//! Text in simple Type1 and TrueType fonts is written with single byte character codes.
+//! The glyph of a code is given by the `/Encoding` of the PDF font: a base encoding
+//! and `/Differences` naming the glyphs of some codes.
+//! Results are keyed by character code so they can be written as a ToUnicode CMap directly.
+
+use std::collections::{BTreeMap, HashMap};
+
+use font::{Font, GlyphId};
+use istring::SmallString;
+use pdf_encoding::Encoding;
+
+use crate::glyph_name_to_unicode;
+
+/// `/Encoding` of a simple PDF font
+#[derive(Clone, Debug, Default)]
+pub struct SimpleEncoding {
+    /// `/BaseEncoding`, the built-in encoding of the font if `None`
+    pub base: Option<Encoding>,
+    /// `/Differences`: code → glyph name
+    pub differences: HashMap<u8, String>,
+}
+impl SimpleEncoding {
+    pub fn new(base: Option<Encoding>) -> Self {
+        SimpleEncoding { base, differences: HashMap::new() }
+    }
+    /// the encoding named `name`, for example `WinAnsiEncoding`
+    pub fn base_from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "StandardEncoding" => Some(Encoding::StandardEncoding),
+            "WinAnsiEncoding" => Some(Encoding::WinAnsiEncoding),
+            "MacRomanEncoding" => Some(Encoding::MacRomanEncoding),
+            "MacExpertEncoding" => Some(Encoding::MacExpertEncoding),
+            _ => None,
+        }
+    }
+    /// add one run of a `/Differences` array: `names` are assigned to consecutive codes starting at `first`
+    pub fn add_differences<S: Into<String>>(&mut self, first: u8, names: impl IntoIterator<Item=S>) {
+        for (code, name) in (first ..= 255).zip(names) {
+            self.differences.insert(code, name.into());
+        }
+    }
+    /// The glyph of `code` in `font`.
+    ///
+    /// A name from `/Differences` that the font does not have is looked up by its unicode value instead.
+    pub fn gid(&self, font: &(dyn Font + Sync + Send), code: u8) -> Option<GlyphId> {
+        if let Some(name) = self.differences.get(&code) {
+            if let Some(gid) = font.gid_for_name(name) {
+                return Some(gid);
+            }
+            let text = glyph_name_to_unicode(name)?;
+            let mut chars = text.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => font.gid_for_unicode_codepoint(c as u32),
+                _ => None,
+            };
+        }
+        if let Some(c) = self.base.and_then(|e| e.forward_map()).and_then(|map| map.get(code)) {
+            if let Some(gid) = font.gid_for_unicode_codepoint(c as u32) {
+                return Some(gid);
+            }
+        }
+        font.gid_for_codepoint(code as u32)
+    }
+    /// the glyph of every code that has one
+    pub fn code_to_gid(&self, font: &(dyn Font + Sync + Send)) -> Vec<(u8, GlyphId)> {
+        (0 ..= 255u8).filter_map(|code| Some((code, self.gid(font, code)?))).collect()
+    }
+}
+
+/// look up glyphs by name for a code → glyph name mapping
+pub fn names_to_gids<'a>(font: &(dyn Font + Sync + Send), names: impl IntoIterator<Item=(u8, &'a str)>) -> Vec<(u8, GlyphId)> {
+    names.into_iter().filter_map(|(code, name)| Some((code, font.gid_for_name(name)?))).collect()
+}
+
+/// label character codes with the labels of their glyphs
+pub fn label_codes(glyphs: &HashMap<GlyphId, SmallString>, codes: &[(u8, GlyphId)]) -> BTreeMap<u8, SmallString> {
+    codes.iter().filter_map(|&(code, gid)| Some((code, glyphs.get(&gid)?.clone()))).collect()
+}
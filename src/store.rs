@@ -0,0 +1,272 @@
+//! Catalog of the fonts in a database and the single file pack format.
+//!
+//! A database directory contains one file per font, named after its PostScript name,
+//! and `catalog.json` describing them.
+//! A pack combines all of them into a single file: `PACK_MAGIC`, the format version
+//! as a little endian `u16`, the length of the postcard encoded `PackIndex` as a little endian `u64`,
+//! the index itself and the database files.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::format::FormatError;
+use crate::{Error, Result};
+
+pub const CATALOG_FILE: &str = "catalog.json";
+/// suffix of files that are being written, see `replace_file`
+pub const TMP_SUFFIX: &str = ".tmp";
+pub const PACK_MAGIC: [u8; 4] = *b"GMPK";
+pub const PACK_VERSION: u16 = 1;
+
+/// information about one font in the database
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CatalogEntry {
+    pub ps_name: String,
+    pub family: Option<String>,
+    pub style: Option<String>,
+    pub version: Option<String>,
+    /// number of labeled glyphs in the database
+    pub glyph_count: usize,
+    /// font file the database was built from
+    pub source_path: String,
+    pub source_hash: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Catalog {
+    pub fonts: Vec<CatalogEntry>,
+}
+impl Catalog {
+    /// load the catalog of a database directory, an empty one if it has none
+    pub fn load_dir(dir: &Path) -> Result<Catalog> {
+        let path = dir.join(CATALOG_FILE);
+        if !path.is_file() {
+            return Ok(Catalog::default());
+        }
+        serde_json::from_slice(&std::fs::read(path)?).map_err(Error::Catalog)
+    }
+    pub fn save_dir(&self, dir: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(Error::Catalog)?;
+        replace_file(&dir.join(CATALOG_FILE), &data)
+    }
+    /// add `entry`, replacing an existing entry with the same PostScript name
+    pub fn insert(&mut self, entry: CatalogEntry) {
+        match self.fonts.iter_mut().find(|e| e.ps_name == entry.ps_name) {
+            Some(e) => *e = entry,
+            None => self.fonts.push(entry),
+        }
+    }
+    pub fn get(&self, ps_name: &str) -> Option<&CatalogEntry> {
+        self.fonts.iter().find(|e| e.ps_name == ps_name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PackEntry {
+    pub info: CatalogEntry,
+    /// position of the database file, relative to the end of the index
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PackIndex {
+    pub fonts: Vec<PackEntry>,
+}
+
+/// a pack file opened for reading
+///
+/// Only the index is read when opening, databases are read on demand.
+pub struct Pack {
+    path: PathBuf,
+    index: PackIndex,
+    data_start: u64,
+}
+impl Pack {
+    /// Open the pack at `path` and read its index.
+    ///
+    /// Lengths in the file are checked against its size before anything is allocated.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Pack> {
+        let path = path.into();
+        let mut file = File::open(&path)?;
+        let file_len = file.metadata()?.len();
+        let corrupt = |message: String| Error::Corrupt { path: path.clone(), message };
+        let mut head = [0; 6];
+        file.read_exact(&mut head)?;
+        if head[.. 4] != PACK_MAGIC {
+            return Err(Error::Database(FormatError::InvalidMagic));
+        }
+        let version = u16::from_le_bytes([head[4], head[5]]);
+        if version != PACK_VERSION {
+            return Err(Error::Database(FormatError::UnsupportedVersion(version)));
+        }
+        let mut len = [0; 8];
+        file.read_exact(&mut len)?;
+        let index_len = u64::from_le_bytes(len);
+        let index_start = file.stream_position()?;
+        if index_len > file_len - index_start {
+            return Err(corrupt(format!("index of {index_len} bytes does not fit in the file")));
+        }
+        let mut index_data = vec![0; index_len as usize];
+        file.read_exact(&mut index_data)?;
+        let index: PackIndex = postcard::from_bytes(&index_data).map_err(FormatError::from)?;
+        let data_start = index_start + index_len;
+        let data_len = file_len - data_start;
+        for e in index.fonts.iter() {
+            if e.offset.checked_add(e.len).is_none_or(|end| end > data_len) {
+                return Err(corrupt(format!("database of {} is outside of the file", e.info.ps_name)));
+            }
+        }
+        Ok(Pack { path, index, data_start })
+    }
+    pub fn catalog(&self) -> impl Iterator<Item=&CatalogEntry> {
+        self.index.fonts.iter().map(|e| &e.info)
+    }
+    /// read the database file of `ps_name`
+    pub fn read(&self, ps_name: &str) -> Result<Option<Vec<u8>>> {
+        let Some(entry) = self.index.fonts.iter().find(|e| e.info.ps_name == ps_name) else {
+            return Ok(None);
+        };
+        let mut file = File::open(&self.path)?;
+        // the pack may have been replaced since its index was read
+        if self.data_start + entry.offset + entry.len > file.metadata()?.len() {
+            return Err(Error::Corrupt { path: self.path.clone(), message: format!("database of {ps_name} is outside of the file") });
+        }
+        file.seek(SeekFrom::Start(self.data_start + entry.offset))?;
+        let mut data = vec![0; entry.len as usize];
+        file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+}
+
+/// the file `path` is written to before it is renamed, see `replace_file`
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(TMP_SUFFIX);
+    tmp.into()
+}
+
+/// Write `data` to `path + TMP_SUFFIX` and rename it to `path`.
+///
+/// Files are never modified in place: readers see either the old or the new file,
+/// and a mapping of the old file (see `mapped::MappedDb::open`) keeps its content.
+pub fn replace_file(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = tmp_path(path);
+    std::fs::write(&tmp, data)?;
+    rename(&tmp, path)
+}
+
+fn rename(tmp: &Path, path: &Path) -> Result<()> {
+    if let Err(e) = std::fs::rename(tmp, path) {
+        let _ = std::fs::remove_file(tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// write a pack containing `fonts`, each with its database file
+pub fn write_pack(path: &Path, fonts: impl IntoIterator<Item=(CatalogEntry, Vec<u8>)>) -> Result<()> {
+    let mut index = PackIndex::default();
+    let mut blobs = vec![];
+    let mut offset = 0;
+    for (info, data) in fonts {
+        index.fonts.push(PackEntry { info, offset, len: data.len() as u64 });
+        offset += data.len() as u64;
+        blobs.push(data);
+    }
+    let index_data = postcard::to_allocvec(&index).map_err(FormatError::from)?;
+
+    let tmp = tmp_path(path);
+    let mut file = std::io::BufWriter::new(File::create(&tmp)?);
+    file.write_all(&PACK_MAGIC)?;
+    file.write_all(&PACK_VERSION.to_le_bytes())?;
+    file.write_all(&(index_data.len() as u64).to_le_bytes())?;
+    file.write_all(&index_data)?;
+    for blob in blobs {
+        file.write_all(&blob)?;
+    }
+    file.flush()?;
+    drop(file);
+    rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::temp_path;
+
+    fn entry(ps_name: &str) -> CatalogEntry {
+        CatalogEntry {
+            ps_name: ps_name.into(),
+            family: Some("Test".into()),
+            style: None,
+            version: None,
+            glyph_count: 1,
+            source_path: format!("{ps_name}.otf"),
+            source_hash: 7,
+        }
+    }
+
+    fn write_test_pack(name: &str) -> PathBuf {
+        let path = temp_path(name);
+        let fonts = vec![
+            (entry("Test-Regular"), b"regular".to_vec()),
+            (entry("Test-Empty"), vec![]),
+            (entry("Test-Bold"), b"bold data".to_vec()),
+        ];
+        write_pack(&path, fonts).unwrap();
+        path
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let path = write_test_pack("round-trip.pack");
+        let pack = Pack::open(&path).unwrap();
+        let names: Vec<_> = pack.catalog().map(|e| e.ps_name.as_str()).collect();
+        assert_eq!(names, ["Test-Regular", "Test-Empty", "Test-Bold"]);
+        assert_eq!(pack.catalog().next(), Some(&entry("Test-Regular")));
+        assert_eq!(pack.read("Test-Regular").unwrap().unwrap(), b"regular");
+        assert_eq!(pack.read("Test-Empty").unwrap().unwrap(), b"");
+        assert_eq!(pack.read("Test-Bold").unwrap().unwrap(), b"bold data");
+        assert!(pack.read("Test-Italic").unwrap().is_none());
+        assert!(!tmp_path(&path).exists());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reject_truncated_pack() {
+        let path = write_test_pack("truncated.pack");
+        let data = std::fs::read(&path).unwrap();
+
+        // the last database is cut short
+        std::fs::write(&path, &data[.. data.len() - 1]).unwrap();
+        assert!(matches!(Pack::open(&path), Err(Error::Corrupt { .. })));
+
+        // the index is cut short
+        std::fs::write(&path, &data[.. 20]).unwrap();
+        assert!(matches!(Pack::open(&path), Err(Error::Corrupt { .. })));
+
+        // the header is cut short
+        std::fs::write(&path, &data[.. 4]).unwrap();
+        assert!(matches!(Pack::open(&path), Err(Error::Io(_))));
+
+        std::fs::write(&path, b"GMDB\x05\x00").unwrap();
+        assert!(matches!(Pack::open(&path), Err(Error::Database(FormatError::InvalidMagic))));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn read_replaced_pack() {
+        let path = write_test_pack("replaced.pack");
+        let pack = Pack::open(&path).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[.. data.len() - 4]).unwrap();
+        assert_eq!(pack.read("Test-Regular").unwrap().unwrap(), b"regular");
+        assert!(matches!(pack.read("Test-Bold"), Err(Error::Corrupt { .. })));
+        std::fs::remove_file(path).unwrap();
+    }
+}
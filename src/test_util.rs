@@ -0,0 +1,67 @@
+//! Outlines and databases shared by the tests.
+
+use std::path::PathBuf;
+
+use pathfinder_content::outline::{Contour, Outline};
+use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_geometry::vector::Vector2F;
+
+use crate::ShapeDb;
+
+/// an outline of closed polygons
+pub(crate) fn outline(contours: &[&[(f32, f32)]]) -> Outline {
+    let mut outline = Outline::new();
+    for points in contours {
+        let mut c = Contour::new();
+        for &(x, y) in points.iter() {
+            c.push_endpoint(Vector2F::new(x, y));
+        }
+        c.close();
+        outline.push_contour(c);
+    }
+    outline
+}
+
+/// font matrix of a font with 1000 units per em
+pub(crate) fn matrix() -> Transform2F {
+    Transform2F::from_scale(0.001)
+}
+
+/// distinct glyphs in font units, with their labels
+pub(crate) fn shapes() -> Vec<(Outline, &'static str)> {
+    vec![
+        (outline(&[&[(0., 0.), (500., 0.), (500., 500.), (0., 500.)]]), "square"),
+        (outline(&[&[(0., 0.), (600., 0.), (300., 700.)]]), "triangle"),
+        (outline(&[&[(0., 0.), (400., 0.), (400., 100.), (100., 100.), (100., 700.), (0., 700.)]]), "L"),
+        (outline(&[
+            &[(0., 0.), (600., 0.), (600., 600.), (0., 600.)],
+            &[(200., 200.), (200., 400.), (400., 400.), (400., 200.)],
+        ]), "frame"),
+    ]
+}
+
+/// a database of `shapes`
+pub(crate) fn db() -> ShapeDb<String> {
+    let mut db = ShapeDb::new();
+    for (outline, label) in shapes() {
+        db.add_outline(&outline, matrix(), label.to_string());
+    }
+    db
+}
+
+/// a path in the temporary directory that is unique to this test run
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("glyphmatcher-{}-{name}", std::process::id()))
+}
+
+/// a new database directory with a database of `shapes` for each of `fonts`
+pub(crate) fn db_dir(name: &str, fonts: &[&str]) -> PathBuf {
+    let dir = temp_path(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = db().to_bytes(0).unwrap();
+    for ps_name in fonts {
+        std::fs::write(dir.join(ps_name), &data).unwrap();
+    }
+    dir
+}
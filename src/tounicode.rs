@@ -0,0 +1,186 @@
+//! Writing glyph labels as a PDF ToUnicode CMap.
+//!
+//! Without a code mapping the character codes are the glyph ids, two bytes each,
+//! as used by Identity-H encoded CID fonts.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use font::GlyphId;
+use istring::SmallString;
+use itertools::Itertools;
+
+use crate::{Error, Result};
+
+/// at most this many entries may be in one bfchar or bfrange block
+const MAX_BLOCK: usize = 100;
+
+/// single mappings `(code, units)` and ranges `(first code, last code, first unit)`, see `ToUnicode::split`
+type Split<'a> = (Vec<(u32, &'a [u16])>, Vec<(u32, u32, u16)>);
+
+/// mapping from character codes to unicode, ready to be written as a CMap
+pub struct ToUnicode {
+    /// code → UTF-16 code units
+    entries: BTreeMap<u32, Vec<u16>>,
+    /// bytes per character code
+    code_bytes: usize,
+}
+impl ToUnicode {
+    /// codes are the glyph ids, fails for glyph ids above 0xFFFF
+    pub fn from_glyphs(glyphs: &HashMap<GlyphId, SmallString>) -> Result<Self> {
+        Self::from_codes(glyphs, glyphs.keys().map(|gid| (gid.0, *gid)), 2)
+    }
+    /// Map the character codes of `codes` to the label of their glyph.
+    ///
+    /// `code_bytes` is the length of a code in the font's encoding, 1 for simple fonts.
+    /// Codes of unlabeled glyphs are left out.
+    pub fn from_codes(glyphs: &HashMap<GlyphId, SmallString>, codes: impl IntoIterator<Item=(u32, GlyphId)>, code_bytes: usize) -> Result<Self> {
+        Self::from_labels(codes.into_iter().filter_map(|(code, gid)| Some((code, &**glyphs.get(&gid)?))), code_bytes)
+    }
+    /// Labels keyed by character code, e.g. from `FontDb::check_codes` with `code_bytes` 1.
+    ///
+    /// Fails if `code_bytes` is not 1 to 4 or a code does not fit in `code_bytes`.
+    pub fn from_labels<'a>(labels: impl IntoIterator<Item=(u32, &'a str)>, code_bytes: usize) -> Result<Self> {
+        if !(1 ..= 4).contains(&code_bytes) {
+            return Err(Error::InvalidCodeLength(code_bytes));
+        }
+        let max = ((1u64 << (8 * code_bytes)) - 1) as u32;
+        let mut entries = BTreeMap::new();
+        for (code, s) in labels {
+            if code > max {
+                return Err(Error::CodeOutOfRange { code, code_bytes });
+            }
+            if !s.is_empty() {
+                entries.insert(code, s.encode_utf16().collect());
+            }
+        }
+        Ok(ToUnicode { entries, code_bytes })
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Split the entries into single mappings and ranges `(first code, last code, first unit)`.
+    ///
+    /// Ranges only contain single code units and only increment the last byte of both code and unicode.
+    fn split(&self) -> Split<'_> {
+        let mut chars = vec![];
+        let mut ranges = vec![];
+        let mut entries = self.entries.iter().peekable();
+        while let Some((&code, units)) = entries.next() {
+            let mut last = code;
+            if let [unit] = units[..] {
+                while let Some((&next, next_units)) = entries.peek() {
+                    let n = next - code;
+                    if next == last + 1 && next & 0xFF != 0
+                        && next_units[..] == [unit.wrapping_add(n as u16)]
+                        && (unit & 0xFF) as u32 + n <= 0xFF
+                    {
+                        last = next;
+                        entries.next();
+                    } else {
+                        break;
+                    }
+                }
+                if last > code {
+                    ranges.push((code, last, unit));
+                    continue;
+                }
+            }
+            chars.push((code, &units[..]));
+        }
+        (chars, ranges)
+    }
+    fn code(&self, code: u32) -> String {
+        format!("<{:01$X}>", code, 2 * self.code_bytes)
+    }
+    /// the CMap stream
+    pub fn write(&self) -> String {
+        let mut out = String::new();
+        out.push_str("/CIDInit /ProcSet findresource begin\n");
+        out.push_str("12 dict begin\n");
+        out.push_str("begincmap\n");
+        out.push_str("/CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n");
+        out.push_str("/CMapName /Adobe-Identity-UCS def\n");
+        out.push_str("/CMapType 2 def\n");
+        out.push_str("1 begincodespacerange\n");
+        let max = (1u64 << (8 * self.code_bytes)) - 1;
+        writeln!(out, "{} {}", self.code(0), self.code(max as u32)).unwrap();
+        out.push_str("endcodespacerange\n");
+
+        let (chars, ranges) = self.split();
+        for block in chars.chunks(MAX_BLOCK) {
+            writeln!(out, "{} beginbfchar", block.len()).unwrap();
+            for &(code, units) in block {
+                writeln!(out, "{} <{}>", self.code(code), units.iter().map(|u| format!("{:04X}", u)).join("")).unwrap();
+            }
+            out.push_str("endbfchar\n");
+        }
+        for block in ranges.chunks(MAX_BLOCK) {
+            writeln!(out, "{} beginbfrange", block.len()).unwrap();
+            for &(first, last, unit) in block {
+                writeln!(out, "{} {} <{:04X}>", self.code(first), self.code(last), unit).unwrap();
+            }
+            out.push_str("endbfrange\n");
+        }
+
+        out.push_str("endcmap\n");
+        out.push_str("CMapName currentdict /CMap defineresource pop\n");
+        out.push_str("end\n");
+        out.push_str("end\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_ranges() {
+        let labels = [(0x41, "A"), (0x42, "B"), (0x43, "C"), (0x45, "x"), (0x46, "ff")];
+        let map = ToUnicode::from_labels(labels, 1).unwrap();
+        let (chars, ranges) = map.split();
+        assert_eq!(ranges, [(0x41, 0x43, 0x41)]);
+        assert_eq!(chars, [(0x45, &[0x78][..]), (0x46, &[0x66, 0x66][..])]);
+    }
+
+    #[test]
+    fn ranges_stop_at_last_byte() {
+        let labels = [(0xFE, "a"), (0xFF, "b"), (0x100, "c"), (0x101, "d")];
+        let map = ToUnicode::from_labels(labels, 2).unwrap();
+        let (chars, ranges) = map.split();
+        assert_eq!(ranges, [(0xFE, 0xFF, 0x61), (0x100, 0x101, 0x63)]);
+        assert!(chars.is_empty());
+
+        let labels = [(0x10, "\u{FF}"), (0x11, "\u{100}")];
+        let map = ToUnicode::from_labels(labels, 1).unwrap();
+        let (chars, ranges) = map.split();
+        assert!(ranges.is_empty());
+        assert_eq!(chars.len(), 2);
+    }
+
+    #[test]
+    fn write_cmap() {
+        let mut glyphs = HashMap::new();
+        glyphs.insert(GlyphId(3), SmallString::from("A"));
+        glyphs.insert(GlyphId(4), SmallString::from("B"));
+        glyphs.insert(GlyphId(7), SmallString::from("fi"));
+        glyphs.insert(GlyphId(9), SmallString::from(""));
+        let cmap = ToUnicode::from_glyphs(&glyphs).unwrap().write();
+        assert!(cmap.contains("1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n"));
+        assert!(cmap.contains("1 beginbfchar\n<0007> <00660069>\nendbfchar\n"));
+        assert!(cmap.contains("1 beginbfrange\n<0003> <0004> <0041>\nendbfrange\n"));
+        assert!(!cmap.contains("<0009>"));
+    }
+
+    #[test]
+    fn reject_invalid_codes() {
+        assert!(matches!(ToUnicode::from_labels([(1, "a")], 0), Err(Error::InvalidCodeLength(0))));
+        assert!(matches!(ToUnicode::from_labels([(1, "a")], 5), Err(Error::InvalidCodeLength(5))));
+        assert!(matches!(ToUnicode::from_labels([(0x100, "a")], 1), Err(Error::CodeOutOfRange { code: 0x100, code_bytes: 1 })));
+        assert!(ToUnicode::from_labels([(u32::MAX, "a")], 4).is_ok());
+    }
+}
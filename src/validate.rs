@@ -0,0 +1,120 @@
+//! Checking the labels of an embedded font against shape matches.
+//!
+//! Some PDFs embed fonts with scrambled cmaps or glyph names (`g123`, shifted private use mappings).
+//! Such fonts label many glyphs differently than the reference font with the same shapes.
+
+use std::collections::HashMap;
+
+use font::GlyphId;
+use istring::SmallString;
+
+use crate::layers::LabelSource;
+
+/// fraction of compared glyphs that may disagree before the labels of a font are considered unreliable
+pub const DEFAULT_THRESHOLD: f32 = 0.2;
+
+/// fonts with fewer compared glyphs are never flagged
+const MIN_COMPARED: usize = 4;
+
+/// a glyph whose own label differs from its shape match
+#[derive(Clone, Debug)]
+pub struct Disagreement {
+    pub gid: GlyphId,
+    /// labels of the glyph in the embedded font
+    pub own: Vec<SmallString>,
+    pub shape: SmallString,
+}
+
+/// result of `validate_labels`
+#[derive(Clone, Debug, Default)]
+pub struct Validation {
+    /// glyphs that have both labels of their own and a shape match
+    pub compared: usize,
+    pub agreed: usize,
+    pub disagreements: Vec<Disagreement>,
+    /// glyphs with labels of their own but no shape match
+    pub unmatched: usize,
+    /// `disagreements / compared`
+    pub disagreement: f32,
+    /// the disagreement is above the threshold
+    pub unreliable: bool,
+}
+
+/// result of `FontDb::validate_font`, the labels of each source validated separately
+#[derive(Clone, Debug, Default)]
+pub struct FontValidation {
+    pub sources: Vec<(LabelSource, Validation)>,
+}
+impl FontValidation {
+    pub fn get(&self, source: LabelSource) -> Option<&Validation> {
+        self.sources.iter().find(|(s, _)| *s == source).map(|(_, v)| v)
+    }
+    /// the labels of some source are unreliable
+    pub fn unreliable(&self) -> bool {
+        self.sources.iter().any(|(_, v)| v.unreliable)
+    }
+}
+
+/// Compare the labels of a font (see `layers::source_labels`) with the labels of the shape matches.
+///
+/// A glyph agrees if any of its own labels equals the shape label.
+pub fn validate_labels(own: &[(GlyphId, SmallString)], shapes: &HashMap<GlyphId, SmallString>, threshold: f32) -> Validation {
+    let mut by_gid: HashMap<GlyphId, Vec<SmallString>> = HashMap::new();
+    for (gid, label) in own {
+        by_gid.entry(*gid).or_default().push(label.clone());
+    }
+
+    let mut v = Validation::default();
+    for (gid, labels) in by_gid {
+        let Some(shape) = shapes.get(&gid) else {
+            v.unmatched += 1;
+            continue;
+        };
+        v.compared += 1;
+        if labels.contains(shape) {
+            v.agreed += 1;
+        } else {
+            v.disagreements.push(Disagreement { gid, own: labels, shape: shape.clone() });
+        }
+    }
+    v.disagreements.sort_by_key(|d| d.gid.0);
+    v.disagreement = v.disagreements.len() as f32 / v.compared.max(1) as f32;
+    v.unreliable = v.compared >= MIN_COMPARED && v.disagreement > threshold;
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(labels: &[(u32, &str)]) -> Vec<(GlyphId, SmallString)> {
+        labels.iter().map(|&(gid, s)| (GlyphId(gid), SmallString::from(s))).collect()
+    }
+
+    #[test]
+    fn disagreement() {
+        let shapes: HashMap<_, _> = labels(&[(1, "a"), (2, "b"), (3, "c"), (4, "d"), (5, "e")]).into_iter().collect();
+
+        // a glyph agrees if any of its labels matches, glyph 6 has no shape match
+        let own = labels(&[(1, "a"), (2, "x"), (2, "b"), (3, "c"), (4, "d"), (5, "f"), (6, "g")]);
+        let v = validate_labels(&own, &shapes, DEFAULT_THRESHOLD);
+        assert_eq!((v.compared, v.agreed, v.unmatched), (5, 4, 1));
+        assert_eq!(v.disagreements.len(), 1);
+        assert_eq!(v.disagreements[0].gid, GlyphId(5));
+        assert_eq!(&*v.disagreements[0].shape, "e");
+        assert_eq!(v.disagreement, 0.2);
+        assert!(!v.unreliable);
+
+        // shifted labels
+        let own = labels(&[(1, "b"), (2, "c"), (3, "d"), (4, "e"), (5, "a")]);
+        let v = validate_labels(&own, &shapes, DEFAULT_THRESHOLD);
+        assert_eq!(v.agreed, 0);
+        assert_eq!(v.disagreements.iter().map(|d| d.gid.0).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+        assert!(v.unreliable);
+
+        // too few glyphs to judge
+        let v = validate_labels(&own[.. MIN_COMPARED - 1], &shapes, DEFAULT_THRESHOLD);
+        assert_eq!(v.disagreement, 1.);
+        assert!(!v.unreliable);
+    }
+}